@@ -1,5 +1,14 @@
-pub async fn run(bind: &String, refresh_interval: u64) {
-    crate::libs::config::Config::new_empty();
-    crate::libs::runner::refresh_dns_loop(refresh_interval).await;
-    crate::web::server::Server::init(bind).await;
+pub async fn run(
+    config: Option<&str>,
+    bind: &str,
+    refresh_interval: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match config {
+        Some(path) => crate::libs::config::Config::load_from_yaml(path)?,
+        None => crate::libs::config::Config::new_empty(),
+    }
+    crate::libs::api::verify_credentials().await?;
+    crate::libs::api::reconcile_records().await;
+    crate::libs::runner::detect_external_ips().await;
+    crate::libs::supervisor::run(bind, refresh_interval).await
 }
@@ -1,5 +1,11 @@
-pub async fn run(path: &String, bind: &String, refresh_interval: u64) {
-    crate::libs::config::Config::load_from_yaml(path).unwrap();
-    crate::libs::runner::refresh_dns_loop(refresh_interval).await;
-    crate::web::server::Server::init(&bind).await;
+pub async fn run(
+    path: &str,
+    bind: &str,
+    refresh_interval: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::libs::config::Config::load_from_yaml(path)?;
+    crate::libs::api::verify_credentials().await?;
+    crate::libs::api::reconcile_records().await;
+    crate::libs::runner::detect_external_ips().await;
+    crate::libs::supervisor::run(bind, refresh_interval).await
 }
@@ -0,0 +1,92 @@
+use crate::libs::api::DnsRecord;
+use std::collections::BTreeMap;
+
+/// The `records` section of the configuration file.
+#[derive(serde::Serialize)]
+struct ImportedConfig {
+    records: BTreeMap<String, Vec<DnsRecord>>,
+}
+
+/// Prints the records of the given zones as a configuration file.
+pub async fn run(
+    zones: &[String],
+    name: Option<&str>,
+    record_type: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    crate::libs::api::verify_credentials().await?;
+
+    let record_type = record_type.map(|t| t.to_ascii_uppercase());
+    // Imported address records follow this host's address, so they are only
+    // taken when asked for by name or type
+    let with_addresses = name.is_some() || record_type.is_some();
+
+    let mut records = BTreeMap::new();
+    for zone_name in zones {
+        let zone_id = crate::libs::api::get_zone(zone_name).await?;
+
+        let live = match crate::libs::cf::list_records(
+            &crate::libs::api::get_api_client(),
+            zone_id,
+            name,
+            record_type.as_deref(),
+        )
+        .await
+        {
+            Ok(live) => live,
+            Err(e) => {
+                tracing::error!("Failed to list records in zone {}: {}", zone_name, e);
+                return Err(e.into());
+            }
+        };
+
+        let (addresses, others): (Vec<DnsRecord>, Vec<DnsRecord>) = live
+            .into_iter()
+            .partition(|record| record.ip_version().is_some());
+        let live = if with_addresses {
+            addresses.into_iter().chain(others).collect()
+        } else {
+            if !addresses.is_empty() {
+                tracing::warn!(
+                    "Skipping {} A and AAAA records in {}, they would be set to this host's address; select them with --name or --type",
+                    addresses.len(),
+                    zone_name
+                );
+            }
+            others
+        };
+
+        let mut imported: Vec<DnsRecord> = live.into_iter().filter_map(importable).collect();
+        imported.sort_by_key(|record| (record.name.clone(), record.type_name()));
+
+        tracing::info!("Imported {} records from {}", imported.len(), zone_name);
+        records.insert(zone_name.clone(), imported);
+    }
+
+    print!("{}", serde_yaml::to_string(&ImportedConfig { records })?);
+    Ok(())
+}
+
+/// Keeps the fields that belong in the configuration. Address records get
+/// their content from IP detection, so it is left out.
+fn importable(record: DnsRecord) -> Option<DnsRecord> {
+    if let Err(e) = record.record_content() {
+        tracing::warn!(
+            "Skipping {} record {}: {}",
+            record.type_name(),
+            record.name.as_deref().unwrap_or_default(),
+            e
+        );
+        return None;
+    }
+
+    let content = match record.ip_version() {
+        Some(_) => None,
+        None => record.content,
+    };
+
+    Some(DnsRecord {
+        id: None,
+        content,
+        ..record
+    })
+}
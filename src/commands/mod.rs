@@ -1,2 +1,4 @@
 pub mod api;
 pub mod file;
+pub mod import;
+pub mod plan;
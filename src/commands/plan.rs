@@ -0,0 +1,34 @@
+/// Prints the changes the daemon would make to each zone, without making them.
+pub async fn run(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    crate::libs::config::Config::load_from_yaml(path)?;
+    crate::libs::api::verify_credentials().await?;
+    crate::libs::runner::detect_external_ips().await;
+
+    let mut zones: Vec<String> = {
+        let config = crate::libs::config::CONFIG.read().unwrap();
+        config.records.keys().cloned().collect()
+    };
+    zones.sort();
+
+    let mut pending = 0;
+    for zone_name in zones {
+        let changes = match crate::libs::plan::plan_zone(&zone_name).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                tracing::error!("Failed to plan zone {}: {}", zone_name, e);
+                return Err(e);
+            }
+        };
+
+        println!("{}", zone_name);
+        for change in changes {
+            if !matches!(change, crate::libs::plan::Change::Unchanged(_)) {
+                pending += 1;
+            }
+            println!("  {}", change);
+        }
+    }
+
+    println!("{} change(s) pending", pending);
+    Ok(())
+}
@@ -1,14 +1,18 @@
-use cloudflare::framework::client::ClientConfig;
-use cloudflare::framework::client::async_api::Client as AsyncClient;
+use crate::libs::cf::CloudflareError;
+use crate::libs::client::ApiClient;
 use cloudflare::framework::{Environment, auth::Credentials};
 use once_cell::sync::{Lazy, OnceCell};
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
-pub static API_CLIENT: OnceCell<AsyncClient> = OnceCell::new();
+/// Replaced when rotated credentials are loaded.
+pub static API_CLIENT: Lazy<RwLock<Option<Arc<ApiClient>>>> = Lazy::new(|| RwLock::new(None));
 
-pub fn get_api_client() -> &'static AsyncClient {
-    API_CLIENT.get().unwrap()
+/// Whether the client uses a scoped API token rather than the global API key.
+static TOKEN_AUTH: OnceCell<bool> = OnceCell::new();
+
+pub fn get_api_client() -> Arc<ApiClient> {
+    API_CLIENT.read().unwrap().clone().unwrap()
 }
 
 static ZONE_ID_CACHE: Lazy<RwLock<HashMap<String, String>>> =
@@ -31,31 +35,241 @@ pub struct DnsRecord {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxied: Option<bool>,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing_if = "Option::is_none", alias = "type")]
     pub record_type: Option<String>,
+
+    /// MX preference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+
+    /// Structured content of SRV and CAA records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<crate::libs::record::RecordData>,
+
+    /// Name of the IP source to detect this record's address with, instead of the global detection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_source: Option<String>,
+
+    /// Publish private, CGNAT and other non-global addresses instead of refusing them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_non_global: Option<bool>,
+
+    /// What to do when the record is changed in Cloudflare, defaults to `warn`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_drift: Option<crate::libs::record::DriftAction>,
+
+    /// Address is pushed by clients, e.g. routers using `/nic/update`, and left alone by the refresh loop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pushed: Option<bool>,
 }
 
-pub async fn init_cf(
-    cf_email: String,
-    cf_api_key: String,
-) -> Result<(), cloudflare::framework::Error> {
+impl DnsRecord {
+    /// Upper-case record type. Records without a type are treated as A records.
+    pub fn type_name(&self) -> String {
+        self.record_type
+            .as_deref()
+            .unwrap_or("A")
+            .to_ascii_uppercase()
+    }
+
+    /// Whether this is the record with the given name and, if set, type.
+    pub fn is(&self, name: &str, record_type: Option<&str>) -> bool {
+        self.name.as_deref() == Some(name)
+            && record_type.is_none_or(|t| t.eq_ignore_ascii_case(&self.type_name()))
+    }
+
+    pub fn record_content(
+        &self,
+    ) -> Result<crate::libs::record::RecordContent, Box<dyn std::error::Error>> {
+        crate::libs::record::RecordContent::from_parts(
+            &self.type_name(),
+            self.content.as_deref(),
+            self.priority,
+            self.data.as_ref(),
+        )
+    }
+
+    pub fn set_content(&mut self, content: crate::libs::record::RecordContent) {
+        self.record_type = Some(content.record_type().to_string());
+        (self.content, self.priority, self.data) = content.into_parts();
+    }
+
+    /// Whether the live record already has this record's content and settings.
+    pub fn matches_live(&self, live: &DnsRecord) -> bool {
+        self.drift(live).is_empty()
+    }
+
+    /// Describes how the live record differs from this one. TTL and proxied
+    /// are only compared when set.
+    pub fn drift(&self, live: &DnsRecord) -> Vec<String> {
+        let mut drift = Vec::new();
+
+        let expected = self.record_content().ok();
+        let actual = live.record_content().ok();
+        if expected != actual {
+            let describe = |content: Option<crate::libs::record::RecordContent>| {
+                content.map_or("none".to_string(), |c| c.to_string())
+            };
+            drift.push(format!(
+                "content is {}, expected {}",
+                describe(actual),
+                describe(expected)
+            ));
+        }
+        if let Some(ttl) = self.ttl.filter(|ttl| live.ttl != Some(*ttl)) {
+            drift.push(format!(
+                "ttl is {}, expected {}",
+                live.ttl.unwrap_or_default(),
+                ttl
+            ));
+        }
+        if let Some(proxied) = self
+            .proxied
+            .filter(|proxied| live.proxied != Some(*proxied))
+        {
+            drift.push(format!(
+                "proxied is {}, expected {}",
+                live.proxied.unwrap_or_default(),
+                proxied
+            ));
+        }
+
+        drift
+    }
+
+    pub fn is_pushed(&self) -> bool {
+        self.pushed.unwrap_or(false)
+    }
+
+    pub fn drift_action(&self) -> crate::libs::record::DriftAction {
+        self.on_drift.unwrap_or_default()
+    }
+
+    /// Address family this record tracks. Records without a type are treated as A records.
+    pub fn ip_version(&self) -> Option<crate::libs::ip::IpVersion> {
+        match &self.record_type {
+            Some(record_type) => crate::libs::ip::IpVersion::from_record_type(record_type),
+            None => Some(crate::libs::ip::IpVersion::V4),
+        }
+    }
+
+    /// Checks that the content is an address of the record's family that may be
+    /// published. Non-global addresses are refused unless the record allows them.
+    pub fn check_address(&self) -> Result<(), CloudflareError> {
+        let Some(version) = self.ip_version() else {
+            return Ok(());
+        };
+
+        let invalid = |reason: String| CloudflareError::InvalidRecord {
+            name: self.name.clone().unwrap_or_default(),
+            reason,
+        };
+        let content = self.content.as_deref().unwrap_or_default();
+
+        let ip: std::net::IpAddr = match content.parse() {
+            Ok(ip) if version.matches(&ip) => ip,
+            _ => {
+                return Err(invalid(format!(
+                    "{} is not a valid {} address",
+                    content, version
+                )));
+            }
+        };
+
+        let scope = crate::libs::ip::scope::classify(&ip);
+        if !scope.is_global() && !self.allow_non_global.unwrap_or(false) {
+            return Err(invalid(format!(
+                "refusing to publish {} address {}, set allow_non_global to publish it",
+                scope, ip
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds a client for the credentials without making it the current one.
+pub fn build_client(credentials: Credentials) -> Result<Arc<ApiClient>, reqwest::Error> {
     let environment = Environment::Production;
 
-    let credentials: Credentials = Credentials::UserAuthKey {
-        email: cf_email,
-        key: cf_api_key,
+    let token_auth = matches!(credentials, Credentials::UserAuthToken { .. });
+    let client = ApiClient::new(credentials, environment)?;
+
+    let _ = TOKEN_AUTH.set(token_auth);
+    Ok(Arc::new(client))
+}
+
+pub fn set_api_client(client: Arc<ApiClient>) {
+    *API_CLIENT.write().unwrap() = Some(client);
+}
+
+/// Checks the current client's credentials, see [`verify_client`].
+pub async fn verify_credentials() -> Result<(), CloudflareError> {
+    verify_client(&get_api_client()).await
+}
+
+/// Checks that the API token is active and may edit DNS records in every
+/// configured zone, or that the global API key is accepted.
+pub async fn verify_client(client: &ApiClient) -> Result<(), CloudflareError> {
+    if !TOKEN_AUTH.get().copied().unwrap_or(false) {
+        return match crate::libs::cf::verify_key(client).await {
+            Ok(email) => {
+                tracing::info!("API key verified for {}", email);
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Failed to verify API key: {}", e);
+                Err(e)
+            }
+        };
+    }
+
+    let status = match crate::libs::cf::verify_token(client).await {
+        Ok(status) => status,
+        Err(e) => {
+            tracing::error!("Failed to verify API token: {}", e);
+            return Err(e);
+        }
+    };
+
+    if status != "active" {
+        return Err(CloudflareError::Unauthorized {
+            reason: format!("API token is {}", status),
+        });
+    }
+
+    let zones: Vec<String> = {
+        let config = crate::libs::config::CONFIG.read().unwrap();
+        config.records.keys().cloned().collect()
     };
 
-    let client = AsyncClient::new(credentials, ClientConfig::default(), environment)?;
+    let mut denied: Vec<String> = Vec::new();
+    for zone_name in zones {
+        match crate::libs::cf::get_zone_permissions(client, zone_name.clone()).await {
+            // A token without access to the zone sees it without permissions
+            Ok(permissions) if permissions.is_empty() => {
+                denied.push(format!("{} (no permissions reported)", zone_name))
+            }
+            Ok(permissions) if permissions.iter().any(|p| p == "#dns_records:edit") => {}
+            Ok(_) => denied.push(format!("{} (no DNS edit permission)", zone_name)),
+            Err(e) => denied.push(format!("{} ({})", zone_name, e)),
+        }
+    }
 
-    if API_CLIENT.set(client).is_err() {
-        tracing::warn!("API client was already initialized");
+    if !denied.is_empty() {
+        return Err(CloudflareError::Unauthorized {
+            reason: format!(
+                "API token cannot edit DNS records in: {}",
+                denied.join(", ")
+            ),
+        });
     }
 
+    tracing::info!("API token verified");
     Ok(())
 }
 
-pub async fn get_zone(zone_name: &String) -> Result<String, Box<dyn std::error::Error>> {
+pub async fn get_zone(zone_name: &String) -> Result<String, CloudflareError> {
     // First: check if zone_id is cached
     if let Some(cached) = ZONE_ID_CACHE.read().unwrap().get(zone_name) {
         return Ok(cached.clone());
@@ -78,33 +292,197 @@ pub async fn get_zone(zone_name: &String) -> Result<String, Box<dyn std::error::
     }
 }
 
-pub async fn list_records(
-    zone_name: &String,
-) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+pub async fn list_records(zone_name: &String) -> Result<Vec<DnsRecord>, CloudflareError> {
     let config = crate::libs::config::CONFIG.read().unwrap();
 
-    match config.get_zone_records(&zone_name) {
+    match config.get_zone_records(zone_name) {
         Some(records) => Ok(records.clone()),
-        None => Err(format!("No records found for zone {}", zone_name).into()),
+        None => Err(CloudflareError::NotConfigured {
+            zone: zone_name.clone(),
+            record: None,
+        }),
     }
 }
 
 pub async fn get_record(
     zone_name: &String,
-    record: &String,
-) -> Result<DnsRecord, Box<dyn std::error::Error>> {
+    record: &str,
+    record_type: Option<&str>,
+) -> Result<DnsRecord, CloudflareError> {
     let config = crate::libs::config::CONFIG.read().unwrap();
 
-    match config.get_zone_record(&zone_name, &record) {
+    match config.get_zone_record(zone_name, record, record_type) {
         Some(records) => Ok(records.clone()),
-        None => Err(format!("Record {} not found in zone {}", record, zone_name).into()),
+        None => Err(CloudflareError::NotConfigured {
+            zone: zone_name.clone(),
+            record: Some(record.to_string()),
+        }),
+    }
+}
+
+/// Looks the record up in the zone by name and type, preferring one with the same content.
+async fn find_live_record(
+    zone_id: &str,
+    record: &DnsRecord,
+) -> Result<Option<DnsRecord>, CloudflareError> {
+    let live = crate::libs::cf::list_records(
+        &get_api_client(),
+        zone_id.to_string(),
+        record.name.as_deref(),
+        Some(&record.type_name()),
+    )
+    .await?;
+
+    Ok(pick_live_record(record, live))
+}
+
+/// Picks the live record matching this one, preferring one with the same content.
+pub fn pick_live_record(record: &DnsRecord, mut live: Vec<DnsRecord>) -> Option<DnsRecord> {
+    let content = record.record_content().ok();
+    match live
+        .iter()
+        .position(|r| content.is_some() && r.record_content().ok() == content)
+    {
+        Some(index) => Some(live.swap_remove(index)),
+        None => {
+            if live.len() > 1 {
+                tracing::warn!(
+                    "Found {} {} records named {}, using the first one",
+                    live.len(),
+                    record.type_name(),
+                    record.name.as_deref().unwrap_or_default()
+                );
+            }
+            live.into_iter().next()
+        }
+    }
+}
+
+/// Counts a failed update for every configured record of the zone.
+fn zone_failed(zone_name: &str) {
+    let config = crate::libs::config::CONFIG.read().unwrap();
+    for record in config.records.get(zone_name).into_iter().flatten() {
+        crate::libs::health::record_failed(
+            zone_name,
+            record.name.as_deref().unwrap_or_default(),
+            &record.type_name(),
+        );
+    }
+}
+
+/// Matches the configured records with the ones that exist in Cloudflare, storing
+/// their ids and, for address records, their current content. Static records that
+/// are missing or differ from the configuration are published.
+pub async fn reconcile_records() {
+    let zones: Vec<String> = {
+        let config = crate::libs::config::CONFIG.read().unwrap();
+        config.records.keys().cloned().collect()
+    };
+
+    for zone_name in zones {
+        let zone_id = match get_zone(&zone_name).await {
+            Ok(zone_id) => zone_id,
+            Err(e) => {
+                tracing::error!("Failed to reconcile zone {}: {}", zone_name, e);
+                zone_failed(&zone_name);
+                continue;
+            }
+        };
+
+        let live = match crate::libs::cf::list_records(&get_api_client(), zone_id, None, None).await
+        {
+            Ok(live) => live,
+            Err(e) => {
+                tracing::error!("Failed to list records in zone {}: {}", zone_name, e);
+                zone_failed(&zone_name);
+                continue;
+            }
+        };
+
+        let mut outdated: Vec<DnsRecord> = Vec::new();
+        {
+            let mut config = crate::libs::config::CONFIG.write().unwrap();
+            let Some(records) = config.records.get_mut(&zone_name) else {
+                continue;
+            };
+
+            for record in records.iter_mut() {
+                let name = record.name.clone().unwrap_or_default();
+                let record_type = record.type_name();
+                let candidates: Vec<DnsRecord> = live
+                    .iter()
+                    .filter(|r| r.is(&name, Some(&record_type)))
+                    .cloned()
+                    .collect();
+                let existing = pick_live_record(record, candidates);
+
+                match (&existing, record.ip_version()) {
+                    // Address records are kept up to date by the refresh loop
+                    (Some(existing), Some(_)) => {
+                        record.id = existing.id.clone();
+                        record.content = existing.content.clone();
+                    }
+                    (None, Some(_)) => {}
+                    (Some(existing), None) if record.matches_live(existing) => {
+                        record.id = existing.id.clone();
+                    }
+                    (existing, None) => {
+                        record.id = existing.as_ref().and_then(|r| r.id.clone());
+                        outdated.push(record.clone());
+                    }
+                }
+
+                // Address records match once they took over the live content
+                if existing
+                    .as_ref()
+                    .is_some_and(|existing| record.matches_live(existing))
+                {
+                    crate::libs::health::record_synced(&zone_name, record);
+                }
+
+                tracing::debug!(
+                    "Record {} ({}) {}",
+                    name,
+                    record_type,
+                    if existing.is_some() {
+                        "exists"
+                    } else {
+                        "is missing"
+                    }
+                );
+            }
+        }
+
+        for record in outdated {
+            let name = record.name.clone().unwrap_or_default();
+            match upsert_record(&zone_name, record).await {
+                Ok(_) => tracing::info!("Published static record {}", name),
+                Err(e) => tracing::error!("Failed to publish static record {}: {}", name, e),
+            }
+        }
+
+        tracing::info!("Reconciled records in zone {}", zone_name);
     }
 }
 
 pub async fn upsert_record(
     zone_name: &String,
-    record: DnsRecord,
-) -> Result<DnsRecord, Box<dyn std::error::Error>> {
+    mut record: DnsRecord,
+) -> Result<DnsRecord, CloudflareError> {
+    if let Err(e) = record.check_address() {
+        tracing::warn!("{}", e);
+        return Err(e);
+    }
+
+    if let Err(e) = record.record_content() {
+        let e = CloudflareError::InvalidRecord {
+            name: record.name.clone().unwrap_or_default(),
+            reason: e.to_string(),
+        };
+        tracing::warn!("{}", e);
+        return Err(e);
+    }
+
     let zone_id = match crate::libs::api::get_zone(zone_name).await {
         Ok(zone_id) => zone_id,
         Err(e) => {
@@ -113,42 +491,130 @@ pub async fn upsert_record(
         }
     };
 
-    let exists = {
+    let ip_source = record.ip_source.clone();
+    let allow_non_global = record.allow_non_global;
+    let on_drift = record.on_drift;
+    let pushed = record.pushed;
+
+    // Records that were already published keep their Cloudflare id
+    if record.id.is_none() {
         let config = crate::libs::config::CONFIG.read().unwrap();
-        config
-            .get_zone_record(zone_name, &record.clone().name.unwrap())
-            .is_some()
-    };
+        record.id = config
+            .get_zone_record(
+                zone_name,
+                record.name.as_deref().unwrap_or_default(),
+                Some(&record.type_name()),
+            )
+            .and_then(|existing| existing.id.clone());
+    }
 
-    let result = if exists {
-        crate::libs::cf::update_record(&get_api_client(), zone_id, record).await
-    } else {
-        crate::libs::cf::create_record(&get_api_client(), zone_id, record).await
+    // Unknown locally, but it may already exist in the zone
+    let mut unchanged = None;
+    if record.id.is_none() {
+        match find_live_record(&zone_id, &record).await {
+            Ok(Some(live)) if record.matches_live(&live) => unchanged = Some(live),
+            Ok(Some(live)) => record.id = live.id,
+            Ok(None) => {}
+            Err(e) => {
+                tracing::error!("Failed to look up record: {}", e);
+                return Err(e);
+            }
+        }
+    }
+
+    let record_name = record.name.clone().unwrap_or_default();
+    let record_type = record.type_name();
+    let started = std::time::Instant::now();
+    let result = match unchanged {
+        Some(live) => {
+            tracing::debug!(
+                "Record {} is already up to date",
+                live.name.as_deref().unwrap_or_default()
+            );
+            Ok(live)
+        }
+        None if record.id.is_some() => update_live_record(&zone_id, record).await,
+        None => crate::libs::cf::create_record(&get_api_client(), zone_id, record).await,
     };
 
+    crate::libs::metrics::record_update(started.elapsed(), result.is_ok());
+
     let record = match result {
-        Ok(record) => record,
+        Ok(record) => DnsRecord {
+            ip_source,
+            allow_non_global,
+            on_drift,
+            pushed,
+            ..record
+        },
         Err(e) => {
-            tracing::error!(
-                "Failed to {} record: {}",
-                if exists { "update" } else { "create" },
-                e
-            );
+            tracing::error!("Failed to upsert record: {}", e);
+            crate::libs::health::record_failed(zone_name, &record_name, &record_type);
             return Err(e);
         }
     };
 
+    // Nothing changed in Cloudflare, so the configuration stays as it was
+    if crate::libs::cf::is_dry_run() {
+        return Ok(record);
+    }
+
+    crate::libs::health::record_synced(zone_name, &record);
+
     let mut config = crate::libs::config::CONFIG.write().unwrap();
     match config.upsert_zone_record(zone_name, record.clone()) {
         Ok(_) => Ok(record),
-        Err(e) => Err(e),
+        Err(e) => Err(CloudflareError::InvalidRecord {
+            name: record_name,
+            reason: e.to_string(),
+        }),
+    }
+}
+
+/// Updates the record, looking it up again when the stored id is stale, e.g.
+/// because the record was deleted or recreated outside of this tool. Other
+/// failures are returned as they are.
+async fn update_live_record(
+    zone_id: &str,
+    mut record: DnsRecord,
+) -> Result<DnsRecord, CloudflareError> {
+    let error = match crate::libs::cf::update_record(
+        &get_api_client(),
+        zone_id.to_string(),
+        record.clone(),
+    )
+    .await
+    {
+        Ok(updated) => return Ok(updated),
+        Err(e) if e.is_record_not_found() => e,
+        Err(e) => return Err(e),
+    };
+
+    tracing::warn!(
+        "Failed to update record {}: {}, looking it up again",
+        record.name.as_deref().unwrap_or_default(),
+        error
+    );
+
+    let live = find_live_record(zone_id, &record).await?;
+    match live {
+        Some(live) if live.id != record.id => {
+            record.id = live.id;
+            crate::libs::cf::update_record(&get_api_client(), zone_id.to_string(), record).await
+        }
+        Some(_) => Err(error),
+        None => {
+            record.id = None;
+            crate::libs::cf::create_record(&get_api_client(), zone_id.to_string(), record).await
+        }
     }
 }
 
 pub async fn delete_record(
     zone_name: &String,
-    record: &String,
-) -> Result<DnsRecord, Box<dyn std::error::Error>> {
+    record: &str,
+    record_type: Option<&str>,
+) -> Result<DnsRecord, CloudflareError> {
     let zone_id = match crate::libs::api::get_zone(zone_name).await {
         Ok(zone_id) => zone_id.clone(),
         Err(e) => {
@@ -157,7 +623,7 @@ pub async fn delete_record(
         }
     };
 
-    let record = match get_record(&zone_name, record).await {
+    let mut record = match get_record(zone_name, record, record_type).await {
         Ok(record) => record,
         Err(e) => {
             tracing::error!("Failed to get record: {}", e);
@@ -165,17 +631,58 @@ pub async fn delete_record(
         }
     };
 
-    let record = match crate::libs::cf::delete_record(&get_api_client(), zone_id, record).await {
-        Ok(record) => record,
-        Err(e) => {
-            tracing::error!("Failed to delete record: {}", e);
-            return Err(e);
+    // Never published or not found by reconcile, but it may exist in the zone by now
+    if record.id.is_none() {
+        match find_live_record(&zone_id, &record).await {
+            Ok(live) => record.id = live.and_then(|live| live.id),
+            Err(e) => {
+                tracing::error!("Failed to look up record: {}", e);
+                return Err(e);
+            }
+        }
+    }
+
+    // Records that don't exist in Cloudflare are only dropped from the configuration
+    let record = if record.id.is_none() {
+        tracing::info!(
+            "Record {} does not exist in Cloudflare",
+            record.name.as_deref().unwrap_or_default()
+        );
+        record
+    } else {
+        match crate::libs::cf::delete_record(&get_api_client(), zone_id, record.clone()).await {
+            Ok(record) => record,
+            Err(e) if e.is_record_not_found() => {
+                tracing::info!(
+                    "Record {} was already deleted in Cloudflare",
+                    record.name.as_deref().unwrap_or_default()
+                );
+                record
+            }
+            Err(e) => {
+                tracing::error!("Failed to delete record: {}", e);
+                return Err(e);
+            }
         }
     };
 
+    if crate::libs::cf::is_dry_run() {
+        return Ok(record);
+    }
+
+    crate::libs::health::record_removed(
+        zone_name,
+        record.name.as_deref().unwrap_or_default(),
+        &record.type_name(),
+    );
+
+    let name = record.name.clone().unwrap_or_default();
     let mut config = crate::libs::config::CONFIG.write().unwrap();
-    match config.delete_zone_record(zone_name, &record.clone().name.unwrap()) {
+    match config.delete_zone_record(zone_name, &name, Some(&record.type_name())) {
         Ok(_) => Ok(record),
-        Err(e) => Err(e),
+        Err(e) => Err(CloudflareError::InvalidRecord {
+            name,
+            reason: e.to_string(),
+        }),
     }
 }
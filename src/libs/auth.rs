@@ -0,0 +1,285 @@
+use crate::libs::api::DnsRecord;
+use base64::Engine;
+
+/// Clients allowed to use the management API. Without clients only reads are
+/// allowed, unless `allow_anonymous` opens the API to anyone.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub clients: Vec<AuthClient>,
+
+    /// Let anyone change records when no clients are configured
+    pub allow_anonymous: bool,
+}
+
+/// What a client may do with the records it has access to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Read,
+    /// Read, create, update and delete records
+    Write,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// A client authenticating with a bearer token, or with HTTP Basic username and password.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AuthClient {
+    /// Shown in the audit log
+    pub name: String,
+
+    #[serde(default)]
+    pub token: Option<String>,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    #[serde(default)]
+    pub scope: Scope,
+
+    /// Zones the client may access, all when empty
+    #[serde(default)]
+    pub zones: Vec<String>,
+
+    /// Record names the client may read or change, e.g. `*.home.example.com`; all when empty
+    #[serde(default)]
+    pub records: Vec<String>,
+}
+
+impl AuthClient {
+    fn matches(&self, credentials: &Credentials) -> bool {
+        match credentials {
+            Credentials::Bearer(token) => self
+                .token
+                .as_deref()
+                .is_some_and(|expected| constant_time_eq(expected, token)),
+            Credentials::Basic { username, password } => {
+                self.username.as_deref() == Some(username.as_str())
+                    && self
+                        .password
+                        .as_deref()
+                        .is_some_and(|expected| constant_time_eq(expected, password))
+            }
+        }
+    }
+
+    /// Whether the client may use the zone, and the record when given, with the scope.
+    pub fn allows(&self, scope: Scope, zone: &str, record: Option<&str>) -> bool {
+        if scope > self.scope {
+            return false;
+        }
+        if !self.zones.is_empty() && !self.zones.iter().any(|z| z.eq_ignore_ascii_case(zone)) {
+            return false;
+        }
+        match record {
+            Some(record) if !self.records.is_empty() => self
+                .records
+                .iter()
+                .any(|pattern| matches_pattern(pattern, record)),
+            _ => true,
+        }
+    }
+
+    /// Whether the client may use every zone and record with the scope.
+    pub fn allows_all(&self, scope: Scope) -> bool {
+        scope <= self.scope && self.zones.is_empty() && self.records.is_empty()
+    }
+
+    /// The records of the zone the client may read.
+    pub fn readable_records(&self, zone: &str, mut records: Vec<DnsRecord>) -> Vec<DnsRecord> {
+        records.retain(|record| {
+            self.allows(
+                Scope::Read,
+                zone,
+                Some(record.name.as_deref().unwrap_or_default()),
+            )
+        });
+        records
+    }
+}
+
+/// Credentials sent in an `Authorization` header.
+pub enum Credentials {
+    Bearer(String),
+    Basic { username: String, password: String },
+}
+
+impl Credentials {
+    pub fn from_header(value: &str) -> Option<Self> {
+        let (scheme, value) = value.trim().split_once(' ')?;
+        let value = value.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return Some(Credentials::Bearer(value.to_string()));
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(value)
+                .ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (username, password) = decoded.split_once(':')?;
+            return Some(Credentials::Basic {
+                username: username.to_string(),
+                password: password.to_string(),
+            });
+        }
+        None
+    }
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Whether callers without credentials may use the scope.
+    pub fn allows_anonymous(&self, scope: Scope) -> bool {
+        !self.is_enabled() && (scope == Scope::Read || self.allow_anonymous)
+    }
+
+    /// The configured client the credentials belong to.
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<&AuthClient> {
+        self.clients
+            .iter()
+            .find(|client| client.matches(credentials))
+    }
+}
+
+/// Case-insensitive match of a record name against a pattern where `*` matches any characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard, the whole name must match
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    let (expected, actual) = (expected.as_bytes(), actual.as_bytes());
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(scope: Scope, zones: &[&str], records: &[&str]) -> AuthClient {
+        AuthClient {
+            name: "test".to_string(),
+            token: None,
+            username: None,
+            password: None,
+            scope,
+            zones: zones.iter().map(|zone| zone.to_string()).collect(),
+            records: records.iter().map(|record| record.to_string()).collect(),
+        }
+    }
+
+    fn record(name: &str) -> DnsRecord {
+        serde_json::from_value(serde_json::json!({ "name": name, "type": "A" })).unwrap()
+    }
+
+    fn names(records: &[DnsRecord]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record.name.as_deref().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn patterns_match_case_insensitively() {
+        assert!(matches_pattern(
+            "*.home.example.com",
+            "NAS.Home.example.com"
+        ));
+        assert!(matches_pattern("home.example.com", "home.example.com"));
+        assert!(matches_pattern("a*c*e", "abcde"));
+        assert!(!matches_pattern("*.home.example.com", "home.example.com"));
+        assert!(!matches_pattern(
+            "home.example.com",
+            "home.example.com.evil"
+        ));
+    }
+
+    #[test]
+    fn readable_records_follow_record_patterns() {
+        let records = vec![
+            record("home.example.com"),
+            record("nas.home.example.com"),
+            record("www.example.com"),
+        ];
+
+        let limited = client(Scope::Write, &[], &["*.home.example.com"]);
+        assert_eq!(
+            names(&limited.readable_records("example.com", records.clone())),
+            ["nas.home.example.com"]
+        );
+
+        let unrestricted = client(Scope::Read, &[], &[]);
+        assert_eq!(
+            unrestricted
+                .readable_records("example.com", records.clone())
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn readable_records_are_empty_for_other_zones() {
+        let client = client(Scope::Read, &["example.org"], &[]);
+        assert!(
+            client
+                .readable_records("example.com", vec![record("www.example.com")])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn write_scope_is_refused_to_readers() {
+        let client = client(Scope::Read, &[], &[]);
+        assert!(client.allows(Scope::Read, "example.com", Some("www.example.com")));
+        assert!(!client.allows(Scope::Write, "example.com", Some("www.example.com")));
+    }
+
+    #[test]
+    fn only_unrestricted_clients_are_allowed_everything() {
+        assert!(client(Scope::Read, &[], &[]).allows_all(Scope::Read));
+        assert!(!client(Scope::Read, &[], &[]).allows_all(Scope::Write));
+        assert!(!client(Scope::Write, &["example.com"], &[]).allows_all(Scope::Read));
+        assert!(!client(Scope::Write, &[], &["*.home.example.com"]).allows_all(Scope::Read));
+    }
+}
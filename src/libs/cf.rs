@@ -1,41 +1,390 @@
+use crate::libs::client::{ApiClient, ApiFailure};
+use crate::libs::record::RecordContent;
+use cloudflare::endpoints::account::user;
 use cloudflare::endpoints::dns::dns::{self};
 use cloudflare::endpoints::zones::zone;
-// use cloudflare::framework::OrderDirection;
-use cloudflare::framework::client::async_api::Client as AsyncClient;
-use cloudflare::framework::response::ApiFailure;
-
-fn map_cloudflare_error(e: ApiFailure) -> Box<dyn std::error::Error> {
-    match e {
-        ApiFailure::Error(status, errors) => {
-            println!("HTTP {status}:");
-            for err in errors.errors {
-                println!("Error {}: {}", err.code, err.message);
-                for (k, v) in err.other {
-                    println!("{k}: {v}");
+use cloudflare::framework::endpoint::spec::EndpointSpec;
+use cloudflare::framework::endpoint::{Method, RequestBody, serialize_query};
+use cloudflare::framework::response::{ApiResult, ApiSuccess};
+use once_cell::sync::OnceCell;
+
+/// When set, record changes are logged and returned without calling the API.
+static DRY_RUN: OnceCell<bool> = OnceCell::new();
+
+pub fn set_dry_run(enabled: bool) {
+    if DRY_RUN.set(enabled).is_err() {
+        tracing::warn!("Dry run was already configured");
+    }
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.get().copied().unwrap_or(false)
+}
+
+/// Logs the change a dry run skips and returns the record it would have produced.
+fn planned(
+    action: &str,
+    record: crate::libs::api::DnsRecord,
+) -> Result<crate::libs::api::DnsRecord, CloudflareError> {
+    let content = record
+        .record_content()
+        .map_or("none".to_string(), |content| content.to_string());
+    tracing::info!(
+        "Dry run: would {} {} record {} with {}",
+        action,
+        record.type_name(),
+        record.name.as_deref().unwrap_or_default(),
+        content
+    );
+    Ok(record)
+}
+
+/// Broad class of a failed Cloudflare operation, telling API clients what went wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Credentials are invalid or lack the permission
+    Authentication,
+    /// The record or request was rejected as invalid
+    Validation,
+    /// The zone or record does not exist
+    NotFound,
+    /// A conflicting record already exists
+    Conflict,
+    RateLimited,
+    /// Cloudflare could not be reached or failed to answer
+    Unavailable,
+    Other,
+}
+
+/// Error code and message reported by Cloudflare.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ApiMessage {
+    pub code: u32,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// A failed Cloudflare operation, e.g. `update record www.example.com`.
+#[derive(Debug)]
+pub enum CloudflareError {
+    /// Cloudflare answered with an error status
+    Api {
+        operation: String,
+        status: reqwest::StatusCode,
+        errors: Vec<ApiMessage>,
+    },
+    /// The request could not be sent or its response could not be read
+    Request {
+        operation: String,
+        source: reqwest::Error,
+    },
+    ZoneNotFound {
+        zone: String,
+    },
+    /// The record cannot be sent to Cloudflare as configured
+    InvalidRecord {
+        name: String,
+        reason: String,
+    },
+    /// The zone or record is not managed by this tool
+    NotConfigured {
+        zone: String,
+        record: Option<String>,
+    },
+    /// The credentials work but may not manage the configured zones
+    Unauthorized {
+        reason: String,
+    },
+}
+
+/// Cloudflare error code for a record id that does not exist.
+const RECORD_NOT_FOUND_CODE: u32 = 81044;
+
+/// Cloudflare error codes for a record clashing with an existing one, e.g. an
+/// identical record or a CNAME next to other records, returned with a 400 status.
+const CONFLICT_ERROR_CODES: [u32; 4] = [81053, 81054, 81057, 81058];
+
+/// Cloudflare error codes for invalid or missing credentials, returned with a 400 status.
+const AUTH_ERROR_CODES: [u32; 4] = [9103, 9106, 9109, 10000];
+
+impl CloudflareError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CloudflareError::Api { status, errors, .. } => {
+                if errors.iter().any(|e| AUTH_ERROR_CODES.contains(&e.code)) {
+                    return ErrorKind::Authentication;
+                }
+                if errors
+                    .iter()
+                    .any(|e| CONFLICT_ERROR_CODES.contains(&e.code))
+                {
+                    return ErrorKind::Conflict;
+                }
+                match status.as_u16() {
+                    401 | 403 => ErrorKind::Authentication,
+                    409 => ErrorKind::Conflict,
+                    400 | 422 => ErrorKind::Validation,
+                    404 => ErrorKind::NotFound,
+                    429 => ErrorKind::RateLimited,
+                    500..=599 => ErrorKind::Unavailable,
+                    _ => ErrorKind::Other,
                 }
             }
-            for (k, v) in errors.other {
-                println!("{k}: {v}");
+            CloudflareError::Request { source, .. } => {
+                if source.is_connect() || source.is_timeout() {
+                    ErrorKind::Unavailable
+                } else {
+                    ErrorKind::Other
+                }
+            }
+            CloudflareError::ZoneNotFound { .. } | CloudflareError::NotConfigured { .. } => {
+                ErrorKind::NotFound
             }
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "API request failed",
-            ))
+            CloudflareError::InvalidRecord { .. } => ErrorKind::Validation,
+            CloudflareError::Unauthorized { .. } => ErrorKind::Authentication,
         }
-        ApiFailure::Invalid(reqwest_err) => {
-            println!("Error: {reqwest_err}");
-            Box::new(reqwest_err)
+    }
+
+    /// Whether the record addressed by id does not exist (anymore). Cloudflare
+    /// answers 404, usually with error 81044.
+    pub fn is_record_not_found(&self) -> bool {
+        matches!(self, CloudflareError::Api { status, errors, .. }
+            if *status == reqwest::StatusCode::NOT_FOUND
+                || errors.iter().any(|e| e.code == RECORD_NOT_FOUND_CODE))
+    }
+
+    /// Errors reported by Cloudflare, empty unless it answered with an error.
+    pub fn messages(&self) -> &[ApiMessage] {
+        match self {
+            CloudflareError::Api { errors, .. } => errors,
+            _ => &[],
         }
     }
+
+    /// Wraps a failed request, logging the details Cloudflare returned with it.
+    fn from_failure(operation: impl Into<String>, failure: ApiFailure) -> Self {
+        let operation = operation.into();
+        match failure {
+            ApiFailure::Error(status, errors) => {
+                for err in &errors {
+                    tracing::debug!(
+                        "Cloudflare error {} while trying to {}: {}",
+                        err.code,
+                        operation,
+                        err.message
+                    );
+                }
+                CloudflareError::Api {
+                    operation,
+                    status,
+                    errors,
+                }
+            }
+            ApiFailure::Invalid(source) => CloudflareError::Request { operation, source },
+        }
+    }
+}
+
+impl std::fmt::Display for CloudflareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloudflareError::Api {
+                operation,
+                status,
+                errors,
+            } => {
+                write!(f, "Failed to {}: HTTP {}", operation, status)?;
+                for (i, err) in errors.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{}{} {}", separator, err.code, err.message)?;
+                }
+                Ok(())
+            }
+            CloudflareError::Request { operation, source } => {
+                write!(f, "Failed to {}: {}", operation, source)
+            }
+            CloudflareError::ZoneNotFound { zone } => write!(f, "Zone {} not found", zone),
+            CloudflareError::InvalidRecord { name, reason } => {
+                write!(f, "Invalid record {}: {}", name, reason)
+            }
+            CloudflareError::NotConfigured {
+                zone,
+                record: Some(record),
+            } => write!(f, "Record {} not found in zone {}", record, zone),
+            CloudflareError::NotConfigured { zone, record: None } => {
+                write!(f, "No records found for zone {}", zone)
+            }
+            CloudflareError::Unauthorized { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CloudflareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CloudflareError::Request { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// DNS record as exchanged with the Cloudflare API. The crate's `DnsContent`
+/// has no CAA and keeps SRV as a plain string, so records are created and
+/// updated through the endpoints below instead.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct RecordBody {
+    #[serde(default, skip_serializing)]
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+}
+
+impl ApiResult for RecordBody {}
+
+/// A page of records; a newtype since `ApiResult` cannot be implemented for `Vec` here.
+#[derive(Debug, serde::Deserialize)]
+#[serde(transparent)]
+struct RecordList(Vec<RecordBody>);
+
+impl ApiResult for RecordList {}
+
+struct CreateRecord<'a> {
+    zone_identifier: &'a str,
+    params: RecordBody,
+}
+
+impl EndpointSpec for CreateRecord<'_> {
+    type JsonResponse = RecordBody;
+    type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    #[inline]
+    fn body(&self) -> Option<RequestBody<'_>> {
+        let body = serde_json::to_string(&self.params).unwrap();
+        Some(RequestBody::Json(body))
+    }
+}
+
+struct UpdateRecord<'a> {
+    zone_identifier: &'a str,
+    identifier: &'a str,
+    params: RecordBody,
+}
+
+impl EndpointSpec for UpdateRecord<'_> {
+    type JsonResponse = RecordBody;
+    type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+    fn method(&self) -> Method {
+        Method::PUT
+    }
+    fn path(&self) -> String {
+        format!(
+            "zones/{}/dns_records/{}",
+            self.zone_identifier, self.identifier
+        )
+    }
+    #[inline]
+    fn body(&self) -> Option<RequestBody<'_>> {
+        let body = serde_json::to_string(&self.params).unwrap();
+        Some(RequestBody::Json(body))
+    }
+}
+
+fn record_body(record: &crate::libs::api::DnsRecord) -> Result<RecordBody, CloudflareError> {
+    let name = record.name.clone().unwrap_or_default();
+    let invalid = |reason: String| CloudflareError::InvalidRecord {
+        name: name.clone(),
+        reason,
+    };
+    if name.is_empty() {
+        return Err(invalid("name is missing".to_string()));
+    }
+    let content = record
+        .record_content()
+        .map_err(|e| invalid(e.to_string()))?;
+    let record_type = content.record_type().to_string();
+
+    // Cloudflare reads the SRV priority from the top level as well as from data
+    let srv_priority = match &content {
+        RecordContent::SRV(srv) => Some(srv.priority),
+        _ => None,
+    };
+
+    let (content, priority, data) = content.into_parts();
+
+    Ok(RecordBody {
+        id: String::new(),
+        name: name.clone(),
+        record_type,
+        content,
+        priority: priority.or(srv_priority),
+        data: data
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| invalid(e.to_string()))?,
+        ttl: record.ttl,
+        proxied: record.proxied,
+    })
+}
+
+fn to_dns_record(record: RecordBody) -> crate::libs::api::DnsRecord {
+    let data = record.data.and_then(|mut data| {
+        if record.record_type == "SRV"
+            && let (Some(object), Some(priority)) = (data.as_object_mut(), record.priority)
+        {
+            object.entry("priority").or_insert(priority.into());
+        }
+        serde_json::from_value(data).ok()
+    });
+
+    let mut result = crate::libs::api::DnsRecord {
+        id: Some(record.id),
+        name: Some(record.name),
+        content: record.content,
+        ttl: record.ttl,
+        proxied: record.proxied,
+        record_type: Some(record.record_type),
+        priority: record.priority,
+        data,
+        ip_source: None,
+        allow_non_global: None,
+        on_drift: None,
+        pushed: None,
+    };
+
+    // Keep only the fields the typed content uses, e.g. drop the SRV display content
+    if let Ok(content) = result.record_content() {
+        result.set_content(content);
+    }
+
+    result
 }
 
 pub async fn get_zone(
-    api_client: &AsyncClient,
+    api_client: &ApiClient,
     zone_name: String,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<String, CloudflareError> {
     let zone_list_params = zone::ListZones {
         params: zone::ListZonesParams {
-            name: Some(zone_name),
+            name: Some(zone_name.clone()),
             ..Default::default()
         },
     };
@@ -43,147 +392,285 @@ pub async fn get_zone(
     let response = api_client.request(&zone_list_params);
     match response.await {
         Ok(success) => {
-            return {
-                if success.result.is_empty() {
-                    Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Zone not found",
-                    )))
-                } else {
-                    Ok(success.result.first().unwrap().id.clone())
-                }
-            };
+            if success.result.is_empty() {
+                Err(CloudflareError::ZoneNotFound { zone: zone_name })
+            } else {
+                Ok(success.result.first().unwrap().id.clone())
+            }
         }
-        Err(e) => Err(map_cloudflare_error(e)),
-    }
-}
-
-// pub async fn list_records(
-//     api_client: &AsyncClient,
-//     zone_id: String,
-// ) -> Result<Vec<crate::libs::api::DnsRecord>, Box<dyn std::error::Error>> {
-//     let endpoint = dns::ListDnsRecords {
-//         zone_identifier: &zone_id,
-//         params: dns::ListDnsRecordsParams {
-//             direction: Some(OrderDirection::Ascending),
-//             ..Default::default()
-//         },
-//     };
-
-//     match api_client.request(&endpoint).await {
-//         Ok(success) => {
-//             let mut records: Vec<crate::libs::api::DnsRecord> = vec![];
-//             success.result.iter().for_each(|record| {
-//                 match record.content {
-//                     cloudflare::endpoints::dns::dns::DnsContent::A { content } => {
-//                         records.push(crate::libs::api::DnsRecord {
-//                             id: Some(record.id.clone()),
-//                             name: Some(record.name.clone()),
-//                             content: Some(content.to_string()),
-//                             ttl: Some(record.ttl),
-//                             proxied: Some(record.proxied),
-//                             record_type: Some("A".to_string()),
-//                         });
-//                     }
-//                     _ => (),
-//                 };
-//             });
-//             return Ok(records);
-//         }
-//         Err(e) => Err(map_cloudflare_error(e)),
-//     }
-// }
+        Err(e) => Err(CloudflareError::from_failure(
+            format!("get zone {}", zone_name),
+            e,
+        )),
+    }
+}
+
+/// Returns the status of the API token the client authenticates with, e.g. `active`.
+pub async fn verify_token(api_client: &ApiClient) -> Result<String, CloudflareError> {
+    match api_client.request(&user::GetUserTokenStatus {}).await {
+        Ok(success) => Ok(success.result.status),
+        Err(e) => Err(CloudflareError::from_failure("verify API token", e)),
+    }
+}
+
+/// The user the global API key belongs to, only read to check the key works.
+#[derive(Debug, serde::Deserialize)]
+struct UserBody {
+    #[serde(default)]
+    email: String,
+}
+
+impl ApiResult for UserBody {}
+
+/// `GET /user`, answered for valid global API keys. The crate's
+/// `GetUserDetails` insists on fields not every account has.
+struct GetUser;
+
+impl EndpointSpec for GetUser {
+    type JsonResponse = UserBody;
+    type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+    fn path(&self) -> String {
+        "user".to_string()
+    }
+}
+
+/// Email of the account the global API key belongs to.
+pub async fn verify_key(api_client: &ApiClient) -> Result<String, CloudflareError> {
+    match api_client.request(&GetUser).await {
+        Ok(success) => Ok(success.result.email),
+        Err(e) => Err(CloudflareError::from_failure("verify API key", e)),
+    }
+}
+
+/// Permissions the client has on a zone, e.g. `#dns_records:edit`.
+pub async fn get_zone_permissions(
+    api_client: &ApiClient,
+    zone_name: String,
+) -> Result<Vec<String>, CloudflareError> {
+    let zone_list_params = zone::ListZones {
+        params: zone::ListZonesParams {
+            name: Some(zone_name.clone()),
+            ..Default::default()
+        },
+    };
+
+    match api_client.request(&zone_list_params).await {
+        Ok(success) => match success.result.into_iter().next() {
+            Some(zone) => Ok(zone.permissions),
+            None => Err(CloudflareError::ZoneNotFound { zone: zone_name }),
+        },
+        Err(e) => Err(CloudflareError::from_failure(
+            format!("get permissions for zone {}", zone_name),
+            e,
+        )),
+    }
+}
+
+/// Page size used when listing records.
+const RECORDS_PER_PAGE: u32 = 100;
+
+#[derive(serde::Serialize)]
+struct ListRecordsParams<'a> {
+    name: Option<&'a str>,
+    #[serde(rename = "type")]
+    record_type: Option<&'a str>,
+    page: u32,
+    per_page: u32,
+}
+
+struct ListRecords<'a> {
+    zone_identifier: &'a str,
+    params: ListRecordsParams<'a>,
+}
+
+impl EndpointSpec for ListRecords<'_> {
+    type JsonResponse = RecordList;
+    type ResponseType = ApiSuccess<Self::JsonResponse>;
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+    fn path(&self) -> String {
+        format!("zones/{}/dns_records", self.zone_identifier)
+    }
+    #[inline]
+    fn query(&self) -> Option<String> {
+        serialize_query(&self.params)
+    }
+}
+
+/// Lists the records of a zone, optionally only those with the given name and type.
+pub async fn list_records(
+    api_client: &ApiClient,
+    zone_id: String,
+    name: Option<&str>,
+    record_type: Option<&str>,
+) -> Result<Vec<crate::libs::api::DnsRecord>, CloudflareError> {
+    let mut records = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let endpoint = ListRecords {
+            zone_identifier: &zone_id,
+            params: ListRecordsParams {
+                name,
+                record_type,
+                page,
+                per_page: RECORDS_PER_PAGE,
+            },
+        };
+
+        let success = match api_client.request(&endpoint).await {
+            Ok(success) => success,
+            Err(e) => {
+                return Err(CloudflareError::from_failure(
+                    format!("list records in zone {}", zone_id),
+                    e,
+                ));
+            }
+        };
+
+        let count = success.result.0.len();
+        records.extend(success.result.0.into_iter().map(to_dns_record));
+
+        let total_pages = success
+            .result_info
+            .as_ref()
+            .and_then(|info| info.get("total_pages"))
+            .and_then(|pages| pages.as_u64())
+            .unwrap_or(1);
+
+        if count < RECORDS_PER_PAGE as usize || u64::from(page) >= total_pages {
+            return Ok(records);
+        }
+        page += 1;
+    }
+}
+
+fn record_id(record: &crate::libs::api::DnsRecord) -> Result<String, CloudflareError> {
+    record
+        .id
+        .clone()
+        .ok_or_else(|| CloudflareError::InvalidRecord {
+            name: record.name.clone().unwrap_or_default(),
+            reason: "id is missing".to_string(),
+        })
+}
 
 pub async fn create_record(
-    api_client: &AsyncClient,
+    api_client: &ApiClient,
     zone_id: String,
     record: crate::libs::api::DnsRecord,
-) -> Result<crate::libs::api::DnsRecord, Box<dyn std::error::Error>> {
-    let endpoint = dns::CreateDnsRecord {
+) -> Result<crate::libs::api::DnsRecord, CloudflareError> {
+    let params = record_body(&record)?;
+    if is_dry_run() {
+        return planned("create", record);
+    }
+
+    let endpoint = CreateRecord {
         zone_identifier: &zone_id,
-        params: dns::CreateDnsRecordParams {
-            name: &record.name.unwrap(),
-            content: dns::DnsContent::A {
-                content: record.content.unwrap().parse().unwrap(),
-            },
-            ttl: record.ttl,
-            proxied: record.proxied,
-            priority: None,
-        },
+        params,
     };
 
     match api_client.request(&endpoint).await {
-        Ok(success) => {
-            return {
-                let content_str = match success.result.content {
-                    dns::DnsContent::A { content } => content.to_string(),
-                    _ => "".to_string(),
-                };
-                Ok(crate::libs::api::DnsRecord {
-                    id: Some(success.result.id),
-                    name: Some(success.result.name),
-                    content: Some(content_str),
-                    ttl: Some(success.result.ttl),
-                    proxied: Some(success.result.proxied),
-                    record_type: Some("A".to_string()),
-                })
-            };
-        }
-        Err(e) => Err(map_cloudflare_error(e)),
+        Ok(success) => Ok(to_dns_record(success.result)),
+        Err(e) => Err(CloudflareError::from_failure(
+            format!("create record {}", endpoint.params.name),
+            e,
+        )),
     }
 }
 
 pub async fn update_record(
-    api_client: &AsyncClient,
+    api_client: &ApiClient,
     zone_id: String,
     record: crate::libs::api::DnsRecord,
-) -> Result<crate::libs::api::DnsRecord, Box<dyn std::error::Error>> {
-    let endpoint = dns::UpdateDnsRecord {
+) -> Result<crate::libs::api::DnsRecord, CloudflareError> {
+    let id = record_id(&record)?;
+    let params = record_body(&record)?;
+    if is_dry_run() {
+        return planned("update", record);
+    }
+
+    let endpoint = UpdateRecord {
         zone_identifier: &zone_id,
-        identifier: &record.id.unwrap(),
-        params: dns::UpdateDnsRecordParams {
-            name: &record.name.unwrap(),
-            content: dns::DnsContent::A {
-                content: record.content.unwrap().parse().unwrap(),
-            },
-            ttl: record.ttl,
-            proxied: record.proxied,
-        },
+        identifier: &id,
+        params,
     };
 
     match api_client.request(&endpoint).await {
-        Ok(success) => {
-            return {
-                let content_str = match success.result.content {
-                    dns::DnsContent::A { content } => content.to_string(),
-                    _ => "".to_string(),
-                };
-                Ok(crate::libs::api::DnsRecord {
-                    id: Some(success.result.id),
-                    name: Some(success.result.name),
-                    content: Some(content_str),
-                    ttl: Some(success.result.ttl),
-                    proxied: Some(success.result.proxied),
-                    record_type: Some("A".to_string()),
-                })
-            };
-        }
-        Err(e) => Err(map_cloudflare_error(e)),
+        Ok(success) => Ok(to_dns_record(success.result)),
+        Err(e) => Err(CloudflareError::from_failure(
+            format!("update record {}", endpoint.params.name),
+            e,
+        )),
     }
 }
 
 pub async fn delete_record(
-    api_client: &AsyncClient,
+    api_client: &ApiClient,
     zone_id: String,
     record: crate::libs::api::DnsRecord,
-) -> Result<crate::libs::api::DnsRecord, Box<dyn std::error::Error>> {
+) -> Result<crate::libs::api::DnsRecord, CloudflareError> {
+    let id = record_id(&record)?;
+    if is_dry_run() {
+        return planned("delete", record);
+    }
+
     let endpoint = dns::DeleteDnsRecord {
         zone_identifier: &zone_id,
-        identifier: &record.clone().id.unwrap(),
+        identifier: &id,
     };
 
     match api_client.request(&endpoint).await {
         Ok(_) => Ok(record),
-        Err(e) => Err(map_cloudflare_error(e)),
+        Err(e) => Err(CloudflareError::from_failure(
+            format!(
+                "delete record {}",
+                record.name.as_deref().unwrap_or_default()
+            ),
+            e,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: u16, code: u32) -> CloudflareError {
+        CloudflareError::Api {
+            operation: "create record www.example.com".to_string(),
+            status: reqwest::StatusCode::from_u16(status).unwrap(),
+            errors: vec![ApiMessage {
+                code,
+                message: String::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn duplicate_records_are_conflicts() {
+        for code in CONFLICT_ERROR_CODES {
+            assert_eq!(api_error(400, code).kind(), ErrorKind::Conflict);
+        }
+        assert_eq!(api_error(400, 9005).kind(), ErrorKind::Validation);
+    }
+
+    #[test]
+    fn credential_codes_are_authentication_failures() {
+        assert_eq!(api_error(400, 9109).kind(), ErrorKind::Authentication);
+        assert_eq!(api_error(403, 0).kind(), ErrorKind::Authentication);
+    }
+
+    #[test]
+    fn missing_records_are_found_by_status_or_code() {
+        assert!(api_error(404, 0).is_record_not_found());
+        assert!(api_error(400, 81044).is_record_not_found());
+        assert!(!api_error(400, 81053).is_record_not_found());
     }
 }
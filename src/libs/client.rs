@@ -0,0 +1,301 @@
+use crate::libs::cf::ApiMessage;
+use cloudflare::framework::Environment;
+use cloudflare::framework::auth::{AuthClient, Credentials};
+use cloudflare::framework::endpoint::spec::EndpointSpec;
+use cloudflare::framework::endpoint::{Method, RequestBody};
+use cloudflare::framework::response::{ApiSuccess, ResponseConverter};
+use once_cell::sync::OnceCell;
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// First backoff step, doubled on every retry up to `MAX_DELAY`.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Longer `Retry-After` waits are not honoured, the request fails instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+static POLICY: OnceCell<RetryPolicy> = OnceCell::new();
+
+/// Requests in flight for the account. Shared by all clients, so rebuilding
+/// the client when credentials rotate keeps the cap.
+static PERMITS: OnceCell<Semaphore> = OnceCell::new();
+
+fn permits() -> &'static Semaphore {
+    PERMITS.get_or_init(|| {
+        let policy = POLICY.get().copied().unwrap_or_default();
+        Semaphore::new(policy.max_concurrent.max(1))
+    })
+}
+
+/// How requests to the Cloudflare API are retried and limited.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Requests in flight at once for the account
+    pub max_concurrent: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            max_concurrent: 4,
+        }
+    }
+}
+
+pub fn set_retry_policy(policy: RetryPolicy) {
+    if POLICY.set(policy).is_err() {
+        tracing::warn!("Retry policy was already configured");
+    }
+}
+
+/// A failed request: Cloudflare answered with an error status, or the request
+/// could not be sent or its response not read.
+#[derive(Debug)]
+pub enum ApiFailure {
+    Error(StatusCode, Vec<ApiMessage>),
+    Invalid(reqwest::Error),
+}
+
+/// Body of an error response. Parsed here rather than with the cloudflare
+/// crate, whose `u16` error codes can't hold DNS record errors like 81044.
+#[derive(Default, serde::Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    errors: Vec<ApiMessage>,
+}
+
+/// Cloudflare API client that retries rate limited and transient failures with
+/// jittered exponential backoff, and caps the requests in flight for the account.
+pub struct ApiClient {
+    environment: Environment,
+    credentials: Credentials,
+    http_client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl ApiClient {
+    pub fn new(credentials: Credentials, environment: Environment) -> Result<Self, reqwest::Error> {
+        let policy = POLICY.get().copied().unwrap_or_default();
+        let http_client = reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?;
+
+        Ok(ApiClient {
+            environment,
+            credentials,
+            http_client,
+            policy,
+        })
+    }
+
+    /// Issues an API request, retrying it while the failure is transient.
+    pub async fn request<Endpoint>(
+        &self,
+        endpoint: &Endpoint,
+    ) -> Result<Endpoint::ResponseType, ApiFailure>
+    where
+        Endpoint: EndpointSpec + Send + Sync,
+        Endpoint::ResponseType: ResponseConverter<Endpoint::JsonResponse>,
+    {
+        let method = endpoint.method();
+        let path = endpoint.path();
+        let operation = operation(&method, &path);
+        let mut retries = 0;
+
+        loop {
+            let result = {
+                // The semaphore is never closed
+                let _permit = permits().acquire().await.ok();
+                self.send(endpoint, &operation).await
+            };
+
+            let (failure, retry_after) = match result {
+                Ok(response) => {
+                    if retries > 0 {
+                        tracing::info!(
+                            "Cloudflare request {} {} succeeded after {} retries",
+                            method,
+                            path,
+                            retries
+                        );
+                    }
+                    return Ok(response);
+                }
+                Err(failure) => failure,
+            };
+
+            let Some(delay) = self.retry_delay(&method, &failure, retry_after, retries) else {
+                if retries > 0 {
+                    tracing::warn!(
+                        "Cloudflare request {} {} failed after {} retries",
+                        method,
+                        path,
+                        retries
+                    );
+                }
+                return Err(failure);
+            };
+
+            retries += 1;
+            crate::libs::metrics::cloudflare_retry(&operation);
+            tracing::warn!(
+                "Cloudflare request {} {} failed ({}), retry {}/{} in {:?}",
+                method,
+                path,
+                reason(&failure),
+                retries,
+                self.policy.max_retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// How long to wait before retrying, or `None` when the failure is final.
+    fn retry_delay(
+        &self,
+        method: &Method,
+        failure: &ApiFailure,
+        retry_after: Option<Duration>,
+        retries: u32,
+    ) -> Option<Duration> {
+        if retries >= self.policy.max_retries {
+            return None;
+        }
+
+        // Creating a record is not idempotent, a request that failed on the
+        // way back may still have been applied
+        let idempotent = *method != Method::POST;
+        let retryable = match failure {
+            ApiFailure::Error(status, _) if *status == StatusCode::TOO_MANY_REQUESTS => true,
+            ApiFailure::Error(status, _) => status.is_server_error() && idempotent,
+            ApiFailure::Invalid(e) => e.is_connect() || (e.is_timeout() && idempotent),
+        };
+        if !retryable {
+            return None;
+        }
+
+        match retry_after {
+            Some(delay) if delay > MAX_RETRY_AFTER => None,
+            Some(delay) => Some(delay),
+            None => Some(backoff(retries)),
+        }
+    }
+
+    /// Sends the request once. Failures carry the server's `Retry-After`, if any.
+    async fn send<Endpoint>(
+        &self,
+        endpoint: &Endpoint,
+        operation: &str,
+    ) -> Result<Endpoint::ResponseType, (ApiFailure, Option<Duration>)>
+    where
+        Endpoint: EndpointSpec + Send + Sync,
+        Endpoint::ResponseType: ResponseConverter<Endpoint::JsonResponse>,
+    {
+        let mut request = self
+            .http_client
+            .request(endpoint.method(), endpoint.url(&self.environment));
+
+        if let Some(body) = endpoint.body() {
+            request = match body {
+                RequestBody::Json(json) => request.body(json),
+                RequestBody::Raw(bytes) => request.body(bytes),
+                RequestBody::MultiPart(_) => {
+                    unreachable!("the DNS, zone and token endpoints have no multipart bodies")
+                }
+            };
+            if let Some(content_type) = endpoint.content_type() {
+                request = request.header(reqwest::header::CONTENT_TYPE, content_type.as_ref());
+            }
+        }
+
+        let response = match request.auth(&self.credentials).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                crate::libs::metrics::cloudflare_request(operation, "error");
+                return Err((ApiFailure::Invalid(e), None));
+            }
+        };
+
+        let status = response.status();
+        crate::libs::metrics::cloudflare_request(operation, status.as_str());
+        if !status.is_success() {
+            let retry_after = retry_after(response.headers());
+            let body: ErrorBody = response.json().await.unwrap_or_default();
+            return Err((ApiFailure::Error(status, body.errors), retry_after));
+        }
+
+        if Endpoint::IS_RAW_BODY {
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| (ApiFailure::Invalid(e), None))?;
+            Ok(Endpoint::ResponseType::from_raw(bytes.to_vec()))
+        } else {
+            let success: ApiSuccess<Endpoint::JsonResponse> = response
+                .json()
+                .await
+                .map_err(|e| (ApiFailure::Invalid(e), None))?;
+            Ok(Endpoint::ResponseType::from_json(success))
+        }
+    }
+}
+
+/// Exponential backoff with jitter, between half and all of the step.
+fn backoff(retries: u32) -> Duration {
+    let step = BASE_DELAY
+        .saturating_mul(1 << retries.min(16))
+        .min(MAX_DELAY);
+    step / 2 + (step / 2).mul_f64(rand::random::<f64>())
+}
+
+/// `Retry-After` in seconds, the form Cloudflare sends.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Method and path with identifiers left out, e.g. `PUT zones/:id/dns_records/:id`.
+fn operation(method: &Method, path: &str) -> String {
+    let path: Vec<&str> = path
+        .split('/')
+        .map(|segment| {
+            if segment.len() == 32 && segment.chars().all(|c| c.is_ascii_hexdigit()) {
+                ":id"
+            } else {
+                segment
+            }
+        })
+        .collect();
+    format!("{} {}", method, path.join("/"))
+}
+
+/// Short description of a failure, without the error details.
+fn reason(failure: &ApiFailure) -> String {
+    match failure {
+        ApiFailure::Error(status, _) => status.to_string(),
+        ApiFailure::Invalid(e) => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_bodies_keep_dns_record_codes() {
+        let body: ErrorBody = serde_json::from_str(
+            r#"{"success":false,"errors":[{"code":81044,"message":"Record does not exist."}],"messages":[],"result":null}"#,
+        )
+        .unwrap();
+
+        assert_eq!(body.errors.len(), 1);
+        assert_eq!(body.errors[0].code, 81044);
+        assert_eq!(body.errors[0].message, "Record does not exist.");
+    }
+}
@@ -6,12 +6,27 @@ use std::sync::RwLock;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub records: HashMap<String, Vec<crate::libs::api::DnsRecord>>,
+
+    #[serde(default)]
+    pub ip_detection: crate::libs::ip::DetectionConfig,
+
+    /// Who may use the management API
+    #[serde(default)]
+    pub api_auth: crate::libs::auth::AuthConfig,
+
+    /// Proxies whose `X-Forwarded-For` header is trusted, as addresses or CIDR ranges
+    #[serde(default)]
+    pub trusted_proxies: Vec<crate::libs::ip::network::IpNetwork>,
 }
 
 pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| {
     RwLock::new(Config {
         records: HashMap::new(),
+        ip_detection: Default::default(),
+        api_auth: Default::default(),
+        trusted_proxies: Vec::new(),
     })
 });
 
@@ -19,13 +34,17 @@ impl Config {
     pub fn new_empty() {
         *CONFIG.write().unwrap() = Config {
             records: HashMap::new(),
+            ip_detection: Default::default(),
+            api_auth: Default::default(),
+            trusted_proxies: Vec::new(),
         };
     }
 
     pub fn load_from_yaml(path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let contents = std::fs::read_to_string(path)?;
         let parsed_config: Config = serde_yaml::from_str(&contents)?;
-        println!("Loaded config: {:?}", parsed_config);
+        parsed_config.ip_detection.quorum.validate()?;
+        tracing::debug!("Loaded config: {:?}", parsed_config);
         *CONFIG.write().unwrap() = parsed_config;
         Ok(())
     }
@@ -34,13 +53,15 @@ impl Config {
         self.records.get(zone)
     }
 
+    /// Finds a record by name, and by type when given.
     pub fn get_zone_record(
         &self,
         zone: &String,
-        record: &String,
+        record: &str,
+        record_type: Option<&str>,
     ) -> Option<&crate::libs::api::DnsRecord> {
         match self.get_zone_records(zone) {
-            Some(records) => records.iter().find(|r| r.name == Some(record.clone())),
+            Some(records) => records.iter().find(|r| r.is(record, record_type)),
             None => None,
         }
     }
@@ -48,10 +69,11 @@ impl Config {
     pub fn delete_zone_record(
         &mut self,
         zone: &String,
-        record: &String,
+        record: &str,
+        record_type: Option<&str>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(records) = self.records.get_mut(zone) {
-            records.retain(|r| r.name != Some(record.clone()));
+            records.retain(|r| !r.is(record, record_type));
             if records.is_empty() {
                 self.records.remove(zone);
             }
@@ -77,21 +99,19 @@ impl Config {
         };
 
         // Get or insert zone entry
-        let records = self
-            .records
-            .entry(zone.to_string())
-            .or_insert_with(Vec::new);
+        let records = self.records.entry(zone.to_string()).or_default();
 
-        // Check if the record exists
+        // Records are identified by name and type
+        let record_type = record.type_name();
         if let Some(existing) = records
             .iter_mut()
-            .find(|r| r.name.as_ref() == Some(record_name))
+            .find(|r| r.is(record_name, Some(&record_type)))
         {
             *existing = record;
         } else {
             records.push(record);
         }
 
-        return Ok(());
+        Ok(())
     }
 }
@@ -0,0 +1,132 @@
+use cloudflare::framework::auth::Credentials;
+use once_cell::sync::{Lazy, OnceCell};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// How often secret files are checked for rotated values.
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+static SOURCE: OnceCell<CredentialSource> = OnceCell::new();
+
+/// Secret the current API client was built with, to notice rotation.
+static LOADED_SECRET: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+
+/// A secret given directly or read from a mounted file, e.g. a Kubernetes or Docker secret.
+#[derive(Clone, Debug)]
+pub enum Secret {
+    Value(String),
+    File(PathBuf),
+}
+
+impl Secret {
+    fn read(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Secret::Value(value) => Ok(value.clone()),
+            Secret::File(path) => {
+                let value = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+                let value = value.trim();
+                if value.is_empty() {
+                    return Err(format!("{} is empty", path.display()).into());
+                }
+                Ok(value.to_string())
+            }
+        }
+    }
+}
+
+/// Where the Cloudflare credentials come from.
+#[derive(Clone, Debug)]
+pub enum CredentialSource {
+    Token(Secret),
+    Key { email: String, key: Secret },
+}
+
+impl CredentialSource {
+    fn secret(&self) -> &Secret {
+        match self {
+            CredentialSource::Token(secret) => secret,
+            CredentialSource::Key { key, .. } => key,
+        }
+    }
+
+    fn credentials(&self, secret: String) -> Credentials {
+        match self {
+            CredentialSource::Token(_) => Credentials::UserAuthToken { token: secret },
+            CredentialSource::Key { email, .. } => Credentials::UserAuthKey {
+                email: email.clone(),
+                key: secret,
+            },
+        }
+    }
+}
+
+/// Loads the credentials and builds the API client.
+pub async fn init(source: CredentialSource) -> Result<(), Box<dyn std::error::Error>> {
+    if SOURCE.set(source).is_err() {
+        tracing::warn!("Credentials were already initialized");
+    }
+
+    // Verified by the commands once the configured zones are known
+    reload(false).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Rebuilds the API client when the secret changed. Returns whether it did.
+/// With `verify`, the new client only replaces the current one once its
+/// credentials were verified.
+async fn reload(verify: bool) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let source = SOURCE.get().ok_or("Credentials are not initialized")?;
+    let secret = source.secret().read()?;
+
+    if LOADED_SECRET.read().unwrap().as_deref() == Some(secret.as_str()) {
+        return Ok(false);
+    }
+
+    let client = crate::libs::api::build_client(source.credentials(secret.clone()))?;
+    if verify {
+        crate::libs::api::verify_client(&client)
+            .await
+            .map_err(|e| format!("New credentials failed verification: {}", e))?;
+    }
+
+    crate::libs::api::set_api_client(client);
+    *LOADED_SECRET.write().unwrap() = Some(secret);
+
+    Ok(true)
+}
+
+/// Re-reads secret files periodically and swaps in a new API client when they change.
+pub async fn watch(shutdown: CancellationToken) -> crate::libs::supervisor::TaskResult {
+    let watches_file = SOURCE
+        .get()
+        .is_some_and(|source| matches!(source.secret(), Secret::File(_)));
+
+    if !watches_file {
+        shutdown.cancelled().await;
+        return Ok(());
+    }
+
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                tracing::info!("Credentials watcher stopped");
+                return Ok(());
+            }
+            _ = interval.tick() => {}
+        }
+
+        match reload(true).await {
+            Ok(false) => {}
+            Ok(true) => tracing::info!("Credentials changed, API client rebuilt"),
+            // Keep the current client, the file may be mid-update or the new
+            // secret not yet valid; it is retried on the next check
+            Err(e) => tracing::error!("Failed to reload credentials: {}", e),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::libs::api::DnsRecord;
+use crate::libs::ip::IpVersion;
+use crate::libs::record::DriftAction;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Refresh intervals without a tick after which the loop is considered stuck.
+const STALE_TICKS: u32 = 3;
+
+/// Consecutive failed updates after which a record counts as persistently failing.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Sync intervals the oldest record sync may be old before the service is not ready.
+static MAX_SYNC_INTERVALS: OnceCell<u32> = OnceCell::new();
+
+struct State {
+    started: Instant,
+    refresh_interval: Option<Duration>,
+    /// How often records are checked with Cloudflare, i.e. the drift interval
+    sync_interval: Option<Duration>,
+    last_tick: Option<Instant>,
+    /// Last time each record was found in sync, by record
+    synced: HashMap<String, Instant>,
+    /// Consecutive failed updates by record, e.g. `home.example.com (A) in example.com`
+    failures: HashMap<String, u32>,
+}
+
+static STATE: Lazy<RwLock<State>> = Lazy::new(|| {
+    RwLock::new(State {
+        started: Instant::now(),
+        refresh_interval: None,
+        sync_interval: None,
+        last_tick: None,
+        synced: HashMap::new(),
+        failures: HashMap::new(),
+    })
+});
+
+#[derive(serde::Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(ok: bool, detail: impl Into<String>) -> Self {
+        Check {
+            ok,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Report {
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Report {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let ok = checks.values().all(|check| check.ok);
+        Report {
+            status: if ok { "ok" } else { "fail" },
+            checks,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+pub fn set_max_sync_intervals(intervals: u32) {
+    if MAX_SYNC_INTERVALS.set(intervals).is_err() {
+        tracing::warn!("Readiness sync age was already configured");
+    }
+}
+
+pub fn set_refresh_interval(interval: Duration) {
+    STATE.write().unwrap().refresh_interval = Some(interval);
+}
+
+pub fn set_sync_interval(interval: Duration) {
+    STATE.write().unwrap().sync_interval = Some(interval);
+}
+
+pub fn loop_ticked() {
+    STATE.write().unwrap().last_tick = Some(Instant::now());
+}
+
+fn key(zone: &str, name: &str, record_type: &str) -> String {
+    format!("{} ({}) in {}", name, record_type, zone)
+}
+
+fn record_key(zone: &str, record: &DnsRecord) -> String {
+    key(
+        zone,
+        record.name.as_deref().unwrap_or_default(),
+        &record.type_name(),
+    )
+}
+
+/// Marks the record as matching Cloudflare now.
+pub fn record_synced(zone: &str, record: &DnsRecord) {
+    let key = record_key(zone, record);
+    {
+        let mut state = STATE.write().unwrap();
+        state.failures.remove(&key);
+        state.synced.insert(key, Instant::now());
+    }
+    crate::libs::metrics::record_synced(zone, record);
+}
+
+/// Clears the failures of a record that already has the wanted content, without
+/// counting as a sync since Cloudflare wasn't asked.
+pub fn record_current(zone: &str, record: &DnsRecord) {
+    STATE
+        .write()
+        .unwrap()
+        .failures
+        .remove(&record_key(zone, record));
+}
+
+pub fn record_failed(zone: &str, name: &str, record_type: &str) {
+    let mut state = STATE.write().unwrap();
+    *state
+        .failures
+        .entry(key(zone, name, record_type))
+        .or_default() += 1;
+}
+
+/// Stops tracking a record that is no longer managed.
+pub fn record_removed(zone: &str, name: &str, record_type: &str) {
+    let key = key(zone, name, record_type);
+    {
+        let mut state = STATE.write().unwrap();
+        state.failures.remove(&key);
+        state.synced.remove(&key);
+    }
+    crate::libs::metrics::record_removed(zone, name, record_type);
+}
+
+/// Whether the process is alive and the refresh loop keeps ticking.
+pub fn liveness() -> Report {
+    let state = STATE.read().unwrap();
+    let mut checks = BTreeMap::new();
+
+    let tick = match (state.refresh_interval, state.last_tick) {
+        (Some(interval), Some(last_tick)) => {
+            let age = last_tick.elapsed();
+            Check::new(
+                age <= interval * STALE_TICKS,
+                format!("last tick {}s ago", age.as_secs()),
+            )
+        }
+        // Startup detects addresses and reconciles records before the loop starts
+        _ => Check::new(
+            true,
+            format!("starting, up {}s", state.started.elapsed().as_secs()),
+        ),
+    };
+    checks.insert("refresh_loop", tick);
+
+    Report::new(checks)
+}
+
+/// Whether records are being kept in sync with Cloudflare.
+pub fn readiness() -> Report {
+    let mut checks = BTreeMap::new();
+
+    let client = crate::libs::api::API_CLIENT.read().unwrap().is_some();
+    checks.insert(
+        "cloudflare_client",
+        Check::new(
+            client,
+            if client {
+                "initialized"
+            } else {
+                "not initialized"
+            },
+        ),
+    );
+
+    let config = crate::libs::config::CONFIG.read().unwrap();
+    let records: Vec<(String, &DnsRecord)> = config
+        .records
+        .iter()
+        .flat_map(|(zone, records)| {
+            records
+                .iter()
+                .map(move |record| (record_key(zone, record), record))
+        })
+        .collect();
+
+    // Only records following the detected address need it, the others have
+    // their own source or are pushed by clients
+    let mut known = Vec::new();
+    let mut missing = Vec::new();
+    for version in [IpVersion::V4, IpVersion::V6] {
+        let needed = records.iter().any(|(_, record)| {
+            record.ip_version() == Some(version)
+                && record.ip_source.is_none()
+                && !record.is_pushed()
+        });
+        match crate::libs::ip::get_external_ip_for(version) {
+            Some(ip) => known.push(ip.ip),
+            None if needed => missing.push(version.to_string()),
+            None => {}
+        }
+    }
+    checks.insert(
+        "external_ip",
+        if !missing.is_empty() {
+            Check::new(
+                false,
+                format!("no {} address detected yet", missing.join(" or ")),
+            )
+        } else if known.is_empty() {
+            Check::new(true, "no address needed")
+        } else {
+            Check::new(true, known.join(", "))
+        },
+    );
+
+    let state = STATE.read().unwrap();
+
+    let mut never: Vec<&str> = records
+        .iter()
+        .filter(|(key, _)| !state.synced.contains_key(key))
+        .map(|(key, _)| key.as_str())
+        .collect();
+    never.sort();
+    // Records ignoring drift are not checked again after their first sync
+    let oldest = records
+        .iter()
+        .filter(|(_, record)| record.drift_action() != DriftAction::Ignore)
+        .filter_map(|(key, _)| state.synced.get(key).map(|at| (key, at.elapsed())))
+        .max_by_key(|(_, age)| *age);
+
+    let max_intervals = MAX_SYNC_INTERVALS.get().copied().unwrap_or(3);
+    let sync = match (oldest, state.sync_interval) {
+        _ if records.is_empty() => Check::new(true, "no records configured"),
+        _ if !never.is_empty() => Check::new(false, format!("never synced: {}", never.join(", "))),
+        (Some((key, age)), Some(interval)) => Check::new(
+            age <= interval * max_intervals,
+            format!("oldest sync {}s ago: {}", age.as_secs(), key),
+        ),
+        (Some((key, age)), None) => {
+            Check::new(true, format!("oldest sync {}s ago: {}", age.as_secs(), key))
+        }
+        (None, _) => Check::new(true, "all records synced"),
+    };
+    checks.insert("last_sync", sync);
+
+    let mut failing: Vec<&String> = state
+        .failures
+        .iter()
+        .filter(|(_, count)| **count >= FAILURE_THRESHOLD)
+        .map(|(key, _)| key)
+        .collect();
+    failing.sort();
+    checks.insert(
+        "failing_records",
+        if failing.is_empty() {
+            Check::new(true, "none")
+        } else {
+            Check::new(
+                false,
+                failing
+                    .iter()
+                    .map(|key| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )
+        },
+    );
+
+    Report::new(checks)
+}
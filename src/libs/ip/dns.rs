@@ -0,0 +1,354 @@
+use super::IpVersion;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Asks a DNS server for a special name that resolves to the address the
+/// query came from, e.g. `myip.opendns.com` or `whoami.cloudflare` (TXT, CH).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DnsSource {
+    pub name: String,
+
+    /// Resolver used for IPv4 lookups
+    pub server: SocketAddr,
+
+    /// Resolver used for IPv6 lookups, e.g. `[2620:119:35::35]:53`
+    #[serde(default)]
+    pub server_v6: Option<SocketAddr>,
+
+    pub query: String,
+
+    /// Defaults to A or AAAA depending on the address family
+    pub record_type: Option<QueryType>,
+
+    pub class: QueryClass,
+}
+
+impl Default for DnsSource {
+    fn default() -> Self {
+        Self {
+            name: "OpenDns".to_string(),
+            server: SocketAddr::from(([208, 67, 222, 222], 53)),
+            server_v6: None,
+            query: "myip.opendns.com".to_string(),
+            record_type: None,
+            class: QueryClass::In,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryType {
+    A,
+    Aaaa,
+    Txt,
+}
+
+impl QueryType {
+    fn code(&self) -> u16 {
+        match self {
+            QueryType::A => 1,
+            QueryType::Aaaa => 28,
+            QueryType::Txt => 16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryClass {
+    #[default]
+    In,
+    Ch,
+}
+
+impl QueryClass {
+    fn code(&self) -> u16 {
+        match self {
+            QueryClass::In => 1,
+            QueryClass::Ch => 3,
+        }
+    }
+}
+
+impl DnsSource {
+    pub fn server(&self, version: IpVersion) -> Option<SocketAddr> {
+        match version {
+            IpVersion::V4 => Some(self.server),
+            IpVersion::V6 => self.server_v6,
+        }
+    }
+
+    pub async fn lookup(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let server = self
+            .server(version)
+            .ok_or_else(|| format!("{} has no resolver for {}", self.name, version))?;
+
+        let record_type = self.record_type.unwrap_or(match version {
+            IpVersion::V4 => QueryType::A,
+            IpVersion::V6 => QueryType::Aaaa,
+        });
+
+        let id: u16 = rand::random();
+        let query = build_query(id, &self.query, record_type, self.class)?;
+
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let exchange = async {
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(server).await?;
+            socket.send(&query).await?;
+
+            let mut buf = [0u8; 4096];
+            let len = socket.recv(&mut buf).await?;
+            Ok::<Vec<u8>, std::io::Error>(buf[..len].to_vec())
+        };
+
+        let response = tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| format!("DNS query to {} timed out", server))??;
+
+        parse_answer(id, &response, record_type)
+    }
+}
+
+fn build_query(
+    id: u16,
+    name: &str,
+    record_type: QueryType,
+    class: QueryClass,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut packet = Vec::with_capacity(512);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Standard query with recursion desired, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid DNS name: {}", name).into());
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+
+    packet.extend_from_slice(&record_type.code().to_be_bytes());
+    packet.extend_from_slice(&class.code().to_be_bytes());
+
+    Ok(packet)
+}
+
+fn parse_answer(
+    id: u16,
+    packet: &[u8],
+    record_type: QueryType,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let truncated = || "Truncated DNS response";
+
+    if packet.len() < 12 || u16::from_be_bytes([packet[0], packet[1]]) != id {
+        return Err("Unexpected DNS response".into());
+    }
+
+    let flags = u16::from_be_bytes([packet[2], packet[3]]);
+    if flags & 0x8000 == 0 {
+        return Err("DNS response is not an answer".into());
+    }
+    if flags & 0x0200 != 0 {
+        return Err("DNS response was truncated".into());
+    }
+    if flags & 0x000f != 0 {
+        return Err(format!("DNS server returned rcode {}", flags & 0x000f).into());
+    }
+
+    let questions = u16::from_be_bytes([packet[4], packet[5]]);
+    let answers = u16::from_be_bytes([packet[6], packet[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(packet, offset).ok_or_else(truncated)? + 4;
+    }
+
+    for _ in 0..answers {
+        offset = skip_name(packet, offset).ok_or_else(truncated)?;
+        let header = packet.get(offset..offset + 10).ok_or_else(truncated)?;
+        let answer_type = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        offset += 10;
+
+        let data = packet.get(offset..offset + length).ok_or_else(truncated)?;
+        offset += length;
+
+        if answer_type != record_type.code() {
+            continue;
+        }
+
+        return match record_type {
+            QueryType::A => <[u8; 4]>::try_from(data)
+                .map(|octets| IpAddr::from(octets).to_string())
+                .map_err(|_| "Malformed A record".into()),
+            QueryType::Aaaa => <[u8; 16]>::try_from(data)
+                .map(|octets| IpAddr::from(octets).to_string())
+                .map_err(|_| "Malformed AAAA record".into()),
+            QueryType::Txt => {
+                let text = data
+                    .split_first()
+                    .and_then(|(len, rest)| rest.get(..*len as usize))
+                    .ok_or("Malformed TXT record")?;
+                Ok(String::from_utf8_lossy(text)
+                    .trim()
+                    .trim_matches('"')
+                    .to_string())
+            }
+        };
+    }
+
+    Err("DNS response has no matching answer".into())
+}
+
+/// Returns the offset just past a (possibly compressed) name.
+fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(offset)?;
+        if len == 0 {
+            return Some(offset + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return Some(offset + 2);
+        }
+        offset += 1 + len as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer to `query` holding the given resource records.
+    fn response(query: &[u8], flags: u16, answers: &[&[u8]]) -> Vec<u8> {
+        let mut packet = query.to_vec();
+        packet[2..4].copy_from_slice(&flags.to_be_bytes());
+        packet[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for answer in answers {
+            packet.extend_from_slice(answer);
+        }
+        packet
+    }
+
+    /// Resource record whose name points back at the question (offset 12).
+    fn record(record_type: u16, class: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = vec![0xc0, 0x0c];
+        record.extend_from_slice(&record_type.to_be_bytes());
+        record.extend_from_slice(&class.to_be_bytes());
+        record.extend_from_slice(&[0, 0, 0x0e, 0x10]);
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    #[test]
+    fn build_query_encodes_question() {
+        let query = build_query(0xbeef, "myip.opendns.com.", QueryType::A, QueryClass::In).unwrap();
+
+        let mut expected = vec![0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x04myip\x07opendns\x03com\x00");
+        expected.extend_from_slice(&[0, 1, 0, 1]);
+        assert_eq!(query, expected);
+    }
+
+    #[test]
+    fn build_query_uses_type_and_class() {
+        let query = build_query(1, "whoami.cloudflare", QueryType::Txt, QueryClass::Ch).unwrap();
+        assert_eq!(query[query.len() - 4..], [0, 16, 0, 3]);
+    }
+
+    #[test]
+    fn build_query_rejects_invalid_names() {
+        assert!(build_query(1, "myip..com", QueryType::A, QueryClass::In).is_err());
+        assert!(build_query(1, &"a".repeat(64), QueryType::A, QueryClass::In).is_err());
+    }
+
+    #[test]
+    fn parse_answer_reads_a_record_with_compressed_name() {
+        let query = build_query(7, "myip.opendns.com", QueryType::A, QueryClass::In).unwrap();
+        let packet = response(&query, 0x8180, &[&record(1, 1, &[203, 0, 113, 7])]);
+
+        assert_eq!(
+            parse_answer(7, &packet, QueryType::A).unwrap(),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn parse_answer_skips_answers_of_other_types() {
+        let query = build_query(7, "myip.opendns.com", QueryType::Aaaa, QueryClass::In).unwrap();
+        let cname = record(5, 1, &[0xc0, 0x0c]);
+        let mut v6 = [0u8; 16];
+        v6[..4].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        v6[15] = 1;
+        let packet = response(&query, 0x8180, &[&cname, &record(28, 1, &v6)]);
+
+        assert_eq!(
+            parse_answer(7, &packet, QueryType::Aaaa).unwrap(),
+            "2001:db8::1"
+        );
+    }
+
+    #[test]
+    fn parse_answer_reads_txt_record() {
+        let query = build_query(7, "whoami.cloudflare", QueryType::Txt, QueryClass::Ch).unwrap();
+        let packet = response(&query, 0x8180, &[&record(16, 3, b"\x0d\"203.0.113.7\"")]);
+
+        assert_eq!(
+            parse_answer(7, &packet, QueryType::Txt).unwrap(),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn parse_answer_rejects_short_packets() {
+        let query = build_query(7, "myip.opendns.com", QueryType::A, QueryClass::In).unwrap();
+        let packet = response(&query, 0x8180, &[&record(1, 1, &[203, 0, 113, 7])]);
+
+        for len in [4, packet.len() - 2, packet.len() - 12] {
+            assert!(parse_answer(7, &packet[..len], QueryType::A).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_answer_rejects_truncated_flag() {
+        let query = build_query(7, "myip.opendns.com", QueryType::A, QueryClass::In).unwrap();
+        let packet = response(&query, 0x8380, &[&record(1, 1, &[203, 0, 113, 7])]);
+
+        let error = parse_answer(7, &packet, QueryType::A).unwrap_err();
+        assert_eq!(error.to_string(), "DNS response was truncated");
+    }
+
+    #[test]
+    fn parse_answer_rejects_other_id_and_errors() {
+        let query = build_query(7, "myip.opendns.com", QueryType::A, QueryClass::In).unwrap();
+        let packet = response(&query, 0x8180, &[&record(1, 1, &[203, 0, 113, 7])]);
+        assert!(parse_answer(8, &packet, QueryType::A).is_err());
+
+        let nxdomain = response(&query, 0x8183, &[]);
+        assert!(parse_answer(7, &nxdomain, QueryType::A).is_err());
+
+        let empty = response(&query, 0x8180, &[]);
+        assert!(parse_answer(7, &empty, QueryType::A).is_err());
+    }
+
+    #[test]
+    fn parse_answer_rejects_malformed_address() {
+        let query = build_query(7, "myip.opendns.com", QueryType::A, QueryClass::In).unwrap();
+        let packet = response(&query, 0x8180, &[&record(1, 1, &[203, 0, 113])]);
+        assert!(parse_answer(7, &packet, QueryType::A).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+const NAT_PMP_PORT: u16 = 5351;
+const PCP_VERSION: u8 = 2;
+const PCP_MAP: u8 = 1;
+const SSDP_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+/// Searched for with SSDP; PPPoE gateways only offer WANPPPConnection.
+const SSDP_SEARCH_TARGETS: [&str; 3] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// First retransmission delay for NAT-PMP, PCP and SSDP, doubled on every retry (RFC 6886).
+const INITIAL_RETRANSMIT: Duration = Duration::from_millis(250);
+
+/// Asks the local router for its WAN address, for hosts behind a NAT gateway.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct GatewaySource {
+    pub name: String,
+
+    /// Router address; read from the default route when not set (Linux only).
+    /// When set, UPnP only trusts replies from it
+    pub gateway: Option<Ipv4Addr>,
+
+    /// Protocols to try, in this order
+    pub protocols: Vec<GatewayProtocol>,
+}
+
+impl Default for GatewaySource {
+    fn default() -> Self {
+        Self {
+            name: "Gateway".to_string(),
+            gateway: None,
+            protocols: vec![
+                GatewayProtocol::NatPmp,
+                GatewayProtocol::Pcp,
+                GatewayProtocol::Upnp,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayProtocol {
+    /// UPnP Internet Gateway Device, discovered via SSDP
+    Upnp,
+    /// NAT Port Mapping Protocol (RFC 6886)
+    NatPmp,
+    /// Port Control Protocol (RFC 6887)
+    Pcp,
+}
+
+impl std::fmt::Display for GatewayProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GatewayProtocol::Upnp => write!(f, "UPnP"),
+            GatewayProtocol::NatPmp => write!(f, "NAT-PMP"),
+            GatewayProtocol::Pcp => write!(f, "PCP"),
+        }
+    }
+}
+
+impl GatewaySource {
+    /// Tries each protocol in turn within one deadline, each getting an even
+    /// share of the time left; only IPv4 is reported by gateways.
+    pub async fn lookup(
+        &self,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let deadline = Instant::now() + timeout;
+        let mut errors: Vec<String> = Vec::new();
+
+        for (i, protocol) in self.protocols.iter().enumerate() {
+            let share = deadline.saturating_duration_since(Instant::now())
+                / (self.protocols.len() - i) as u32;
+
+            let attempt = async {
+                match protocol {
+                    GatewayProtocol::Upnp => upnp_external_ip(self.gateway).await,
+                    GatewayProtocol::NatPmp => nat_pmp_external_ip(self.gateway()?).await,
+                    GatewayProtocol::Pcp => pcp_external_ip(self.gateway()?).await,
+                }
+            };
+
+            let result = tokio::time::timeout(share, attempt)
+                .await
+                .unwrap_or_else(|_| Err("gateway did not answer in time".into()));
+
+            match result {
+                Ok(ip) => {
+                    tracing::debug!("{} gateway reported {}", protocol, ip);
+                    return Ok(ip.to_string());
+                }
+                Err(e) => {
+                    tracing::debug!("{} gateway lookup failed: {}", protocol, e);
+                    errors.push(format!("{}: {}", protocol, e));
+                }
+            }
+        }
+
+        Err(format!("No gateway answered ({})", errors.join(", ")).into())
+    }
+
+    fn gateway(&self) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>> {
+        match self.gateway {
+            Some(gateway) => Ok(gateway),
+            None => default_gateway(),
+        }
+    }
+}
+
+/// Reads the IPv4 default gateway from the kernel routing table.
+fn default_gateway() -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>> {
+    let routes = std::fs::read_to_string("/proc/net/route")
+        .map_err(|e| format!("Cannot read routing table, set `gateway` explicitly: {}", e))?;
+
+    // Columns: Iface Destination Gateway ..., addresses in little-endian hex
+    routes
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|columns| columns.len() > 2 && columns[1] == "00000000")
+        .and_then(|columns| u32::from_str_radix(columns[2], 16).ok())
+        .map(|gateway| Ipv4Addr::from(gateway.swap_bytes()))
+        .ok_or_else(|| "No default route found, set `gateway` explicitly".into())
+}
+
+/// Sends `request` with exponential backoff until `accept` recognises a reply.
+/// The caller bounds how long this keeps trying.
+async fn exchange<F>(
+    gateway: SocketAddr,
+    request: &[u8],
+    accept: F,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(&[u8]) -> Option<Result<Ipv4Addr, String>>,
+{
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(gateway).await?;
+
+    let mut buf = [0u8; 1100];
+    let mut wait = INITIAL_RETRANSMIT;
+    loop {
+        socket.send(request).await?;
+
+        let Ok(len) = tokio::time::timeout(wait, socket.recv(&mut buf)).await else {
+            wait *= 2;
+            continue;
+        };
+
+        if let Some(result) = accept(&buf[..len?]) {
+            return Ok(result?);
+        }
+    }
+}
+
+async fn nat_pmp_external_ip(
+    gateway: Ipv4Addr,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>> {
+    // Version 0, opcode 0: external address request
+    exchange((gateway, NAT_PMP_PORT).into(), &[0, 0], |reply| {
+        if reply.len() < 12 || reply[0] != 0 || reply[1] != 128 {
+            return None;
+        }
+        let result = u16::from_be_bytes([reply[2], reply[3]]);
+        if result != 0 {
+            return Some(Err(format!("NAT-PMP result code {}", result)));
+        }
+        let octets: [u8; 4] = reply[8..12].try_into().unwrap();
+        Some(Ok(Ipv4Addr::from(octets)))
+    })
+    .await
+}
+
+/// PCP has no plain "what is my address" request. A MAP with a lifetime of
+/// zero asks the gateway to delete a mapping, so none is ever created, and
+/// the answer still carries the external address.
+async fn pcp_external_ip(
+    gateway: Ipv4Addr,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>> {
+    let gateway: SocketAddr = (gateway, NAT_PMP_PORT).into();
+
+    // The request has to carry the client address as seen by the gateway
+    let probe = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    probe.connect(gateway).await?;
+    let client = match probe.local_addr()? {
+        SocketAddr::V4(addr) => addr,
+        SocketAddr::V6(_) => return Err("PCP needs an IPv4 client address".into()),
+    };
+
+    let nonce: [u8; 12] = rand::random();
+    let mut request = Vec::with_capacity(60);
+    request.extend_from_slice(&[PCP_VERSION, PCP_MAP, 0, 0]);
+    // Lifetime
+    request.extend_from_slice(&0u32.to_be_bytes());
+    request.extend_from_slice(&client.ip().to_ipv6_mapped().octets());
+    request.extend_from_slice(&nonce);
+    // UDP, reserved, internal port, suggested external port and address
+    request.extend_from_slice(&[17, 0, 0, 0]);
+    request.extend_from_slice(&client.port().to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&[0u8; 16]);
+
+    let accept = |reply: &[u8]| {
+        if reply.len() < 60 || reply[0] != PCP_VERSION || reply[1] != (0x80 | PCP_MAP) {
+            return None;
+        }
+        if reply[24..36] != nonce {
+            return None;
+        }
+        if reply[3] != 0 {
+            return Some(Err(format!("PCP result code {}", reply[3])));
+        }
+        let octets: [u8; 16] = reply[44..60].try_into().unwrap();
+        Some(match std::net::Ipv6Addr::from(octets).to_ipv4_mapped() {
+            Some(ip) if ip.is_unspecified() => {
+                Err("PCP gateway did not report its external address".to_string())
+            }
+            Some(ip) => Ok(ip),
+            None => Err("PCP gateway reported a non-IPv4 address".to_string()),
+        })
+    };
+
+    exchange(gateway, &request, accept).await
+}
+
+async fn upnp_external_ip(
+    gateway: Option<Ipv4Addr>,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync>> {
+    let location = igd_location(gateway).await?;
+
+    let client = reqwest::Client::new();
+    let description = client
+        .get(&location)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let (service_type, control_url) = wan_connection_service(&description)
+        .ok_or_else(|| format!("{} has no WAN connection service", location))?;
+    let control_url = reqwest::Url::parse(&location)?.join(&control_url)?;
+
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:GetExternalIPAddress xmlns:u=\"{}\"/></s:Body></s:Envelope>",
+        service_type
+    );
+
+    let response = client
+        .post(control_url)
+        .header("Content-Type", "text/xml; charset=\"utf-8\"")
+        .header(
+            "SOAPAction",
+            format!("\"{}#GetExternalIPAddress\"", service_type),
+        )
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let ip = xml_element(&response, "NewExternalIPAddress")
+        .ok_or("Gateway response has no NewExternalIPAddress")?;
+
+    Ok(ip.parse()?)
+}
+
+/// Searches for the gateway with SSDP, repeating the searches with exponential
+/// backoff until one answers. The caller bounds how long this keeps trying.
+async fn igd_location(
+    gateway: Option<Ipv4Addr>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+
+    let mut buf = [0u8; 2048];
+    let mut wait = INITIAL_RETRANSMIT;
+    loop {
+        for target in SSDP_SEARCH_TARGETS {
+            let search = format!(
+                "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+                target
+            );
+            socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+        }
+
+        // Replies to earlier searches still count after a retransmission
+        let retransmit = Instant::now() + wait;
+        while let Ok(received) =
+            tokio::time::timeout_at(retransmit, socket.recv_from(&mut buf)).await
+        {
+            let (len, from) = received?;
+            let reply = String::from_utf8_lossy(&buf[..len]);
+            if let Some(location) = reply_location(&reply, from, gateway) {
+                return Ok(location);
+            }
+        }
+        wait *= 2;
+    }
+}
+
+/// Location of the device description in an SSDP reply from a gateway. Other
+/// devices answer searches too, and when the gateway is known, so could other hosts.
+fn reply_location(reply: &str, from: SocketAddr, gateway: Option<Ipv4Addr>) -> Option<String> {
+    if gateway.is_some_and(|gateway| from.ip() != std::net::IpAddr::V4(gateway)) {
+        return None;
+    }
+    let target = header(reply, "st")?;
+    if !SSDP_SEARCH_TARGETS.contains(&target.as_str()) {
+        return None;
+    }
+    header(reply, "location")
+}
+
+/// Value of a header in an SSDP reply.
+fn header(reply: &str, name: &str) -> Option<String> {
+    reply.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Finds the service type and control URL of the WANIPConnection or
+/// WANPPPConnection service in an IGD device description.
+fn wan_connection_service(description: &str) -> Option<(String, String)> {
+    description
+        .split("<service>")
+        .skip(1)
+        .filter_map(|service| {
+            let service_type = xml_element(service, "serviceType")?;
+            let control_url = xml_element(service, "controlURL")?;
+            Some((service_type, control_url))
+        })
+        .find(|(service_type, _)| {
+            service_type.contains(":WANIPConnection:")
+                || service_type.contains(":WANPPPConnection:")
+        })
+}
+
+fn xml_element(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(target: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: {}\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n",
+            target
+        )
+    }
+
+    #[test]
+    fn gateway_replies_for_any_target_are_accepted() {
+        let from: SocketAddr = "192.168.1.1:1900".parse().unwrap();
+        for target in SSDP_SEARCH_TARGETS {
+            assert_eq!(
+                reply_location(&reply(target), from, None).as_deref(),
+                Some("http://192.168.1.1:5000/rootDesc.xml")
+            );
+        }
+        assert_eq!(
+            reply_location(
+                &reply("urn:schemas-upnp-org:device:MediaServer:1"),
+                from,
+                None
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn replies_from_other_hosts_are_ignored_when_the_gateway_is_set() {
+        let gateway = Some(Ipv4Addr::new(192, 168, 1, 1));
+        let target = SSDP_SEARCH_TARGETS[0];
+
+        let other: SocketAddr = "192.168.1.23:1900".parse().unwrap();
+        assert_eq!(reply_location(&reply(target), other, gateway), None);
+
+        let router: SocketAddr = "192.168.1.1:1900".parse().unwrap();
+        assert!(reply_location(&reply(target), router, gateway).is_some());
+    }
+
+    #[test]
+    fn ppp_connections_are_found_in_descriptions() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1</serviceType>\
+            <controlURL>/ctl/CommonIfCfg</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>\
+            <controlURL>/ctl/PPPConn</controlURL></service>\
+            </serviceList></device></root>";
+
+        assert_eq!(
+            wan_connection_service(description),
+            Some((
+                "urn:schemas-upnp-org:service:WANPPPConnection:1".to_string(),
+                "/ctl/PPPConn".to_string()
+            ))
+        );
+    }
+}
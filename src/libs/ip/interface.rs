@@ -0,0 +1,67 @@
+use super::IpVersion;
+use super::scope::{AddressScope, classify};
+use std::net::IpAddr;
+
+/// Reads the external address straight from a local network interface, for
+/// hosts that have their public address bound on the WAN side.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct InterfaceSource {
+    pub name: String,
+    pub interface: String,
+
+    /// Report a private, CGNAT or unique local address when the interface has no global one
+    #[serde(default)]
+    pub allow_non_global: bool,
+}
+
+impl InterfaceSource {
+    pub fn lookup(
+        &self,
+        version: IpVersion,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let addresses: Vec<IpAddr> = if_addrs::get_if_addrs()?
+            .into_iter()
+            .filter(|iface| iface.name == self.interface)
+            .map(|iface| iface.ip())
+            .collect();
+
+        if addresses.is_empty() {
+            return Err(format!("Interface {} has no addresses", self.interface).into());
+        }
+
+        // Site-local addresses are only reported when the source opts in, for
+        // records that also allow non-global addresses
+        let candidates: Vec<(IpAddr, AddressScope)> = addresses
+            .into_iter()
+            .filter(|ip| version.matches(ip))
+            .map(|ip| (ip, classify(&ip)))
+            .filter(|(_, scope)| {
+                matches!(
+                    scope,
+                    AddressScope::Global
+                        | AddressScope::Private
+                        | AddressScope::Shared
+                        | AddressScope::UniqueLocal
+                )
+            })
+            .collect();
+
+        if let Some((ip, _)) = candidates.iter().find(|(_, scope)| scope.is_global()) {
+            return Ok(ip.to_string());
+        }
+
+        match candidates.first() {
+            Some((ip, _)) if self.allow_non_global => Ok(ip.to_string()),
+            Some((ip, scope)) => Err(format!(
+                "Interface {} has no global {} address, only {} address {}; set allow_non_global to use it",
+                self.interface, version, scope, ip
+            )
+            .into()),
+            None => Err(format!(
+                "Interface {} has no usable {} address",
+                self.interface, version
+            )
+            .into()),
+        }
+    }
+}
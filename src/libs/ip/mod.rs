@@ -0,0 +1,947 @@
+pub mod dns;
+pub mod gateway;
+pub mod interface;
+pub mod network;
+pub mod scope;
+pub mod stun;
+
+use once_cell::sync::Lazy;
+use rand::prelude::{IndexedRandom, SliceRandom};
+use rand::rng;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(serde::Serialize, Clone)]
+pub struct IP {
+    pub ip: String,
+    pub version: IpVersion,
+    pub source: IPSource,
+
+    /// Individual answers when the address was agreed on by quorum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub votes: Option<Vec<Vote>>,
+}
+
+impl IP {
+    pub fn scope(&self) -> Option<scope::AddressScope> {
+        self.ip.parse().ok().map(|ip| scope::classify(&ip))
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Vote {
+    pub source: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Ask a single randomly chosen source
+    #[default]
+    Random,
+    /// Ask several sources in parallel and require agreement
+    Quorum,
+    /// Try sources one after another until one answers
+    Failover,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    pub strategy: Strategy,
+
+    /// Per-source request timeout in seconds
+    pub timeout: u64,
+
+    pub quorum: QuorumConfig,
+
+    pub failover: FailoverConfig,
+
+    /// Whether the built-in public sources are used alongside `sources`
+    pub builtin_sources: bool,
+
+    /// Additional sources; an entry named like a built-in one replaces it
+    pub sources: Vec<IPSource>,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::default(),
+            timeout: 10,
+            quorum: QuorumConfig::default(),
+            failover: FailoverConfig::default(),
+            builtin_sources: true,
+            sources: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct QuorumConfig {
+    /// How many sources to query on each check
+    pub sources: usize,
+
+    /// How many of them must return the same address
+    pub min_agree: usize,
+}
+
+impl QuorumConfig {
+    /// Rejects settings under which no quorum could ever be reached.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_agree == 0 {
+            return Err("ip_detection.quorum.min_agree must be at least 1".to_string());
+        }
+        if self.min_agree > self.sources {
+            return Err(format!(
+                "ip_detection.quorum.min_agree ({}) cannot exceed the number of sources queried ({})",
+                self.min_agree, self.sources
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        Self {
+            sources: 3,
+            min_agree: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct FailoverConfig {
+    /// Source names to try first, in this order; the rest follow
+    pub order: Vec<String>,
+
+    /// Try sources in random order instead
+    pub shuffle: bool,
+
+    /// Consecutive failures after which a source is skipped
+    pub failure_threshold: u32,
+
+    /// Seconds a failing source is skipped before it is tried again
+    pub cooldown: u64,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            order: Vec::new(),
+            shuffle: false,
+            failure_threshold: 3,
+            cooldown: 300,
+        }
+    }
+}
+
+/// Consecutive failures of a source for one address family.
+#[derive(Default)]
+struct SourceHealth {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+static SOURCE_HEALTH: Lazy<RwLock<HashMap<(String, IpVersion), SourceHealth>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+fn is_tripped(source: &str, version: IpVersion) -> bool {
+    SOURCE_HEALTH
+        .read()
+        .unwrap()
+        .get(&(source.to_string(), version))
+        .and_then(|health| health.open_until)
+        .is_some_and(|until| Instant::now() < until)
+}
+
+fn record_success(source: &str, version: IpVersion) {
+    SOURCE_HEALTH
+        .write()
+        .unwrap()
+        .remove(&(source.to_string(), version));
+}
+
+fn record_failure(source: &str, version: IpVersion, settings: &FailoverConfig) {
+    let mut health = SOURCE_HEALTH.write().unwrap();
+    let entry = health.entry((source.to_string(), version)).or_default();
+    entry.failures += 1;
+
+    if entry.failures >= settings.failure_threshold {
+        tracing::warn!(
+            "{} failed {} times in a row for {}, skipping it for {}s",
+            source,
+            entry.failures,
+            version,
+            settings.cooldown
+        );
+        entry.open_until = Some(Instant::now() + Duration::from_secs(settings.cooldown));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    /// DNS record type that holds addresses of this family.
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            IpVersion::V4 => "A",
+            IpVersion::V6 => "AAAA",
+        }
+    }
+
+    pub fn from_record_type(record_type: &str) -> Option<Self> {
+        if record_type.eq_ignore_ascii_case("A") {
+            Some(IpVersion::V4)
+        } else if record_type.eq_ignore_ascii_case("AAAA") {
+            Some(IpVersion::V6)
+        } else {
+            None
+        }
+    }
+
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => IpVersion::V4,
+            IpAddr::V6(_) => IpVersion::V6,
+        }
+    }
+
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        matches!(
+            (self, ip),
+            (IpVersion::V4, IpAddr::V4(_)) | (IpVersion::V6, IpAddr::V6(_))
+        )
+    }
+
+    /// Local address to bind outgoing requests to, so they go out over this family.
+    fn unspecified(&self) -> IpAddr {
+        match self {
+            IpVersion::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpVersion::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+impl std::fmt::Display for IpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpVersion::V4 => write!(f, "IPv4"),
+            IpVersion::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IPSource {
+    #[serde(rename = "ipify_org")]
+    ApifyOrg(ApifyOrg),
+    IpApi(IpApi),
+    IpinfoIo(IpinfoIo),
+    IdentMe(IdentMe),
+    Http(HttpSource),
+    Interface(interface::InterfaceSource),
+    Dns(dns::DnsSource),
+    Stun(stun::StunSource),
+    Gateway(gateway::GatewaySource),
+}
+
+impl IPSource {
+    pub fn name(&self) -> &str {
+        match self {
+            IPSource::ApifyOrg(s) => &s.name,
+            IPSource::IpApi(s) => &s.name,
+            IPSource::IpinfoIo(s) => &s.name,
+            IPSource::IdentMe(s) => &s.name,
+            IPSource::Http(s) => &s.name,
+            IPSource::Interface(s) => &s.name,
+            IPSource::Dns(s) => &s.name,
+            IPSource::Stun(s) => &s.name,
+            IPSource::Gateway(s) => &s.name,
+        }
+    }
+
+    pub fn url(&self, version: IpVersion) -> Option<&str> {
+        let (url, url_v6) = match self {
+            IPSource::ApifyOrg(s) => (Some(&s.url), &s.url_v6),
+            IPSource::IpApi(s) => (Some(&s.url), &s.url_v6),
+            IPSource::IpinfoIo(s) => (Some(&s.url), &s.url_v6),
+            IPSource::IdentMe(s) => (Some(&s.url), &s.url_v6),
+            IPSource::Http(s) => (s.url.as_ref(), &s.url_v6),
+            IPSource::Interface(_)
+            | IPSource::Dns(_)
+            | IPSource::Stun(_)
+            | IPSource::Gateway(_) => (None, &None),
+        };
+
+        match version {
+            IpVersion::V4 => url.map(String::as_str),
+            IpVersion::V6 => url_v6.as_deref(),
+        }
+    }
+
+    pub fn supports(&self, version: IpVersion) -> bool {
+        match self {
+            IPSource::Interface(_) | IPSource::Stun(_) => true,
+            IPSource::Dns(source) => source.server(version).is_some(),
+            IPSource::Gateway(_) => version == IpVersion::V4,
+            _ => self.url(version).is_some(),
+        }
+    }
+
+    /// Built-in sources followed by the configured ones.
+    fn configured(settings: &DetectionConfig) -> Vec<IPSource> {
+        let mut sources = if settings.builtin_sources {
+            vec![
+                IPSource::ApifyOrg(ApifyOrg::new()),
+                IPSource::IpApi(IpApi::new()),
+                IPSource::IpinfoIo(IpinfoIo::new()),
+                IPSource::IdentMe(IdentMe::new()),
+            ]
+        } else {
+            Vec::new()
+        };
+
+        for source in &settings.sources {
+            match sources.iter_mut().find(|s| s.name() == source.name()) {
+                Some(existing) => *existing = source.clone(),
+                None => sources.push(source.clone()),
+            }
+        }
+
+        sources
+    }
+
+    pub async fn get(version: IpVersion) -> Result<IP, Box<dyn std::error::Error + Send + Sync>> {
+        let settings = crate::libs::config::CONFIG
+            .read()
+            .unwrap()
+            .ip_detection
+            .clone();
+
+        let sources: Vec<IPSource> = Self::configured(&settings)
+            .into_iter()
+            .filter(|source| source.supports(version))
+            .collect();
+
+        if sources.is_empty() {
+            return Err(format!("No sources configured for {}", version).into());
+        }
+
+        let timeout = Duration::from_secs(settings.timeout);
+
+        match settings.strategy {
+            Strategy::Random => {
+                let selected = {
+                    let mut rng = rng();
+                    sources.choose(&mut rng).unwrap().clone()
+                };
+
+                let ip = selected.fetch(version, timeout).await?;
+
+                Ok(IP {
+                    ip,
+                    version,
+                    source: selected,
+                    votes: None,
+                })
+            }
+            Strategy::Quorum => Self::get_quorum(sources, version, timeout, &settings.quorum).await,
+            Strategy::Failover => {
+                Self::get_failover(sources, version, timeout, &settings.failover).await
+            }
+        }
+    }
+
+    /// Tries sources in order until one answers, skipping sources that failed
+    /// repeatedly until their cooldown has passed.
+    async fn get_failover(
+        mut sources: Vec<IPSource>,
+        version: IpVersion,
+        timeout: Duration,
+        settings: &FailoverConfig,
+    ) -> Result<IP, Box<dyn std::error::Error + Send + Sync>> {
+        if settings.shuffle {
+            let mut rng = rng();
+            sources.shuffle(&mut rng);
+        } else {
+            // Stable sort keeps the default order for sources not listed
+            sources.sort_by_key(|source| {
+                settings
+                    .order
+                    .iter()
+                    .position(|name| name == source.name())
+                    .unwrap_or(settings.order.len())
+            });
+        }
+
+        let mut candidates: Vec<IPSource> = sources
+            .iter()
+            .filter(|source| !is_tripped(source.name(), version))
+            .cloned()
+            .collect();
+
+        if candidates.is_empty() {
+            tracing::warn!(
+                "All {} sources are cooling down after repeated failures, trying them anyway",
+                version
+            );
+            candidates = sources;
+        }
+
+        let mut errors: Vec<String> = Vec::new();
+        for source in candidates {
+            match source.fetch(version, timeout).await {
+                Ok(ip) => {
+                    record_success(source.name(), version);
+                    if !errors.is_empty() {
+                        tracing::info!(
+                            "{} address from {} after {} failed attempt(s)",
+                            version,
+                            source.name(),
+                            errors.len()
+                        );
+                    }
+                    return Ok(IP {
+                        ip,
+                        version,
+                        source,
+                        votes: None,
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("{} lookup via {} failed: {}", version, source.name(), e);
+                    record_failure(source.name(), version, settings);
+                    errors.push(format!("{}: {}", source.name(), e));
+                }
+            }
+        }
+
+        Err(format!("All {} sources failed ({})", version, errors.join(", ")).into())
+    }
+
+    /// Detects the address using one specific source, selected by name.
+    pub async fn get_from(
+        name: &str,
+        version: IpVersion,
+    ) -> Result<IP, Box<dyn std::error::Error + Send + Sync>> {
+        let settings = crate::libs::config::CONFIG
+            .read()
+            .unwrap()
+            .ip_detection
+            .clone();
+
+        let source = Self::configured(&settings)
+            .into_iter()
+            .find(|source| source.name() == name)
+            .ok_or_else(|| format!("Unknown IP source: {}", name))?;
+
+        if !source.supports(version) {
+            return Err(format!("{} does not support {}", name, version).into());
+        }
+
+        let ip = source
+            .fetch(version, Duration::from_secs(settings.timeout))
+            .await?;
+
+        Ok(IP {
+            ip,
+            version,
+            source,
+            votes: None,
+        })
+    }
+
+    /// Queries several sources in parallel and only accepts an address that
+    /// enough of them agree on.
+    async fn get_quorum(
+        sources: Vec<IPSource>,
+        version: IpVersion,
+        timeout: Duration,
+        settings: &QuorumConfig,
+    ) -> Result<IP, Box<dyn std::error::Error + Send + Sync>> {
+        let selected: Vec<IPSource> = {
+            let mut rng = rng();
+            sources
+                .sample(&mut rng, settings.sources)
+                .cloned()
+                .collect()
+        };
+
+        if selected.len() < settings.min_agree {
+            return Err(format!(
+                "Quorum needs {} agreeing sources but only {} {} sources are available",
+                settings.min_agree,
+                selected.len(),
+                version
+            )
+            .into());
+        }
+
+        let mut requests = tokio::task::JoinSet::new();
+        for source in selected {
+            requests.spawn(async move {
+                let result = source.fetch(version, timeout).await;
+                (source, result)
+            });
+        }
+
+        let mut answers: Vec<(IPSource, String)> = Vec::new();
+        let mut votes: Vec<Vote> = Vec::new();
+        while let Some(joined) = requests.join_next().await {
+            let (source, result) = joined?;
+            match result {
+                Ok(ip) => {
+                    votes.push(Vote {
+                        source: source.name().to_string(),
+                        ip: Some(ip.clone()),
+                        error: None,
+                    });
+                    answers.push((source, ip));
+                }
+                Err(e) => {
+                    tracing::warn!("{} lookup via {} failed: {}", version, source.name(), e);
+                    votes.push(Vote {
+                        source: source.name().to_string(),
+                        ip: None,
+                        error: Some(e.to_string()),
+                    });
+                }
+            }
+        }
+
+        let mut tally: HashMap<&str, usize> = HashMap::new();
+        for (_, ip) in &answers {
+            *tally.entry(ip.as_str()).or_default() += 1;
+        }
+
+        let summary = votes
+            .iter()
+            .map(|vote| match (&vote.ip, &vote.error) {
+                (Some(ip), _) => format!("{}={}", vote.source, ip),
+                (None, Some(e)) => format!("{}=error({})", vote.source, e),
+                (None, None) => format!("{}=none", vote.source),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let winner = tally
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(ip, count)| (ip.to_string(), *count));
+
+        // A tie for the top spot means there is no clear answer
+        let tied = winner
+            .as_ref()
+            .is_some_and(|(_, top)| tally.values().filter(|count| *count == top).count() > 1);
+
+        match winner {
+            Some((ip, count)) if count >= settings.min_agree && !tied => {
+                if tally.len() > 1 {
+                    tracing::warn!(
+                        "{} sources disagree, accepting {} ({} of {} agree): {}",
+                        version,
+                        ip,
+                        count,
+                        votes.len(),
+                        summary
+                    );
+                }
+
+                let source = answers
+                    .into_iter()
+                    .find(|(_, answer)| *answer == ip)
+                    .map(|(source, _)| source)
+                    .unwrap();
+
+                Ok(IP {
+                    ip,
+                    version,
+                    source,
+                    votes: Some(votes),
+                })
+            }
+            _ => {
+                tracing::warn!("No {} quorum reached: {}", version, summary);
+                if tied {
+                    return Err(format!(
+                        "No quorum: sources are tied on the {} address ({})",
+                        version, summary
+                    )
+                    .into());
+                }
+                Err(format!(
+                    "No quorum: fewer than {} of {} sources agree on the {} address ({})",
+                    settings.min_agree,
+                    votes.len(),
+                    version,
+                    summary
+                )
+                .into())
+            }
+        }
+    }
+
+    /// Asks this source for the external address of the given family.
+    async fn fetch(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let result = self.lookup(version, timeout).await;
+        crate::libs::metrics::ip_check(self.name(), version, result.is_ok());
+        result
+    }
+
+    async fn lookup(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let ip = match self {
+            IPSource::ApifyOrg(_)
+            | IPSource::IpApi(_)
+            | IPSource::IpinfoIo(_)
+            | IPSource::IdentMe(_)
+            | IPSource::Http(_) => self.fetch_url(version, timeout).await?,
+            IPSource::Interface(source) => source.lookup(version)?,
+            IPSource::Dns(source) => source.lookup(version, timeout).await?,
+            IPSource::Stun(source) => source.lookup(version, timeout).await?,
+            IPSource::Gateway(source) => source.lookup(timeout).await?,
+        };
+
+        let parsed: IpAddr = ip
+            .parse()
+            .map_err(|_| format!("{} returned an invalid address: {}", self.name(), ip))?;
+        if !version.matches(&parsed) {
+            return Err(
+                format!("{} returned {} for an {} lookup", self.name(), ip, version).into(),
+            );
+        }
+
+        Ok(ip)
+    }
+
+    /// Requests the address from a URL based source and parses the response.
+    async fn fetch_url(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let res = self.request(version, timeout).await?;
+
+        let ip = match self {
+            IPSource::ApifyOrg(_) => serde_json::from_str::<IpFyResponse>(&res)?.ip,
+            IPSource::IpApi(_) => serde_json::from_str::<IpApiResponse>(&res)?.query,
+            IPSource::IpinfoIo(_) => serde_json::from_str::<IpinfoIoResponse>(&res)?.ip,
+            IPSource::IdentMe(_) => serde_json::from_str::<IdentMeResponse>(&res)?.address,
+            IPSource::Http(source) => source.format.extract(&res)?,
+            _ => return Err(format!("{} is not an HTTP source", self.name()).into()),
+        };
+
+        Ok(ip)
+    }
+
+    /// Fetches the body of an HTTP source over the given address family.
+    async fn request(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let url = self
+            .url(version)
+            .ok_or_else(|| format!("{} does not support {}", self.name(), version))?;
+
+        let client = reqwest::Client::builder()
+            .local_address(version.unspecified())
+            .timeout(timeout)
+            .build()?;
+
+        let mut request = client.get(url);
+        if let IPSource::Http(source) = self {
+            for (key, value) in &source.headers {
+                request = request.header(key, value);
+            }
+        }
+
+        Ok(request.send().await?.error_for_status()?.text().await?)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ApifyOrg {
+    pub name: String,
+    pub url: String,
+    pub url_v6: Option<String>,
+}
+
+impl ApifyOrg {
+    pub fn new() -> Self {
+        Self {
+            name: "IpifyOrg".to_string(),
+            url: "https://api.ipify.org?format=json".to_string(),
+            url_v6: Some("https://api6.ipify.org?format=json".to_string()),
+        }
+    }
+}
+
+impl Default for ApifyOrg {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct IpApi {
+    pub name: String,
+    pub url: String,
+    pub url_v6: Option<String>,
+}
+
+impl IpApi {
+    pub fn new() -> Self {
+        Self {
+            name: "IpApi".to_string(),
+            url: "http://ip-api.com/json/".to_string(),
+            url_v6: None,
+        }
+    }
+}
+
+impl Default for IpApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct IpinfoIo {
+    pub name: String,
+    pub url: String,
+    pub url_v6: Option<String>,
+}
+
+impl IpinfoIo {
+    pub fn new() -> Self {
+        Self {
+            name: "IpinfoIo".to_string(),
+            url: "https://ipinfo.io/json".to_string(),
+            url_v6: Some("https://v6.ipinfo.io/json".to_string()),
+        }
+    }
+}
+
+impl Default for IpinfoIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct IdentMe {
+    pub name: String,
+    pub url: String,
+    pub url_v6: Option<String>,
+}
+
+impl IdentMe {
+    pub fn new() -> Self {
+        Self {
+            name: "IdentMe".to_string(),
+            url: "https://ident.me/.json".to_string(),
+            url_v6: Some("https://v6.ident.me/.json".to_string()),
+        }
+    }
+}
+
+impl Default for IdentMe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A user-defined HTTP source, e.g. an internal "what is my IP" service.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct HttpSource {
+    pub name: String,
+
+    #[serde(default)]
+    pub url: Option<String>,
+
+    #[serde(default)]
+    pub url_v6: Option<String>,
+
+    #[serde(default)]
+    pub format: ResponseFormat,
+
+    /// Extra request headers; never serialized as they may carry credentials
+    #[serde(default, skip_serializing)]
+    pub headers: HashMap<String, String>,
+}
+
+/// How the address is extracted from a response body.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    /// The whole body is the address
+    #[default]
+    Text,
+    /// Dot-separated path to a string field, e.g. `data.ip` or `addresses.0`
+    Json(JsonPath),
+    /// First capture group, or the whole match when the pattern has none
+    Regex(Pattern),
+}
+
+/// A JSON field path, split into its keys when the configuration is loaded.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    path: String,
+    keys: Vec<String>,
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        let keys: Vec<String> = path.split('.').map(str::to_string).collect();
+        if keys.iter().any(|key| key.trim().is_empty()) {
+            return Err(format!("Invalid JSON path {:?}: empty field name", path));
+        }
+        Ok(JsonPath { path, keys })
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.path
+    }
+}
+
+/// A regular expression, compiled when the configuration is loaded.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(regex::Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        regex::Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(|e| format!("Invalid pattern {:?}: {}", pattern, e))
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl ResponseFormat {
+    fn extract(&self, body: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            ResponseFormat::Text => Ok(body.trim().to_string()),
+            ResponseFormat::Json(path) => {
+                let value: serde_json::Value = serde_json::from_str(body)?;
+                let mut current = &value;
+                for key in &path.keys {
+                    current = match current {
+                        serde_json::Value::Array(items) => {
+                            key.parse::<usize>().ok().and_then(|i| items.get(i))
+                        }
+                        _ => current.get(key),
+                    }
+                    .ok_or_else(|| format!("Field {} not found in response", path.path))?;
+                }
+                current
+                    .as_str()
+                    .map(|ip| ip.trim().to_string())
+                    .ok_or_else(|| format!("Field {} is not a string", path.path).into())
+            }
+            ResponseFormat::Regex(Pattern(pattern)) => {
+                let captures = pattern
+                    .captures(body)
+                    .ok_or_else(|| format!("Pattern {} did not match response", pattern))?;
+                let matched = captures.get(1).or_else(|| captures.get(0)).unwrap();
+                Ok(matched.as_str().trim().to_string())
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct IpFyResponse {
+    ip: String,
+}
+
+#[derive(serde::Deserialize)]
+struct IpApiResponse {
+    query: String,
+}
+
+#[derive(serde::Deserialize)]
+struct IpinfoIoResponse {
+    ip: String,
+}
+
+#[derive(serde::Deserialize)]
+struct IdentMeResponse {
+    address: String,
+}
+
+pub static EXTERNAL_IP: Lazy<RwLock<Option<Arc<IP>>>> = Lazy::new(|| RwLock::new(None));
+
+pub static EXTERNAL_IPV6: Lazy<RwLock<Option<Arc<IP>>>> = Lazy::new(|| RwLock::new(None));
+
+fn external_ip_slot(version: IpVersion) -> &'static RwLock<Option<Arc<IP>>> {
+    match version {
+        IpVersion::V4 => &EXTERNAL_IP,
+        IpVersion::V6 => &EXTERNAL_IPV6,
+    }
+}
+
+/// Stores the IP in the slot matching its address family.
+pub fn set_external_ip(ip: IP) {
+    let mut lock = external_ip_slot(ip.version).write().unwrap();
+    crate::libs::metrics::external_ip(&ip, lock.as_ref().map(|previous| previous.ip.as_str()));
+    *lock = Some(Arc::new(ip));
+}
+
+pub fn get_external_ip_for(version: IpVersion) -> Option<IP> {
+    external_ip_slot(version)
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|ip| (**ip).clone())
+}
+
+pub fn get_external_ip() -> Option<IP> {
+    get_external_ip_for(IpVersion::V4)
+}
+
+pub fn get_external_ipv6() -> Option<IP> {
+    get_external_ip_for(IpVersion::V6)
+}
@@ -0,0 +1,125 @@
+use std::net::IpAddr;
+
+/// An address range in CIDR notation, e.g. `10.0.0.0/8`, or a single address.
+/// Parsed when the configuration is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(*ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(*ip), 128),
+            _ => return false,
+        };
+
+        if self.prefix == 0 {
+            return true;
+        }
+        let host_bits = bits - self.prefix;
+        network >> host_bits == ip >> host_bits
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.as_str(), None),
+        };
+
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid network {:?}: not an IP address", value))?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid network {:?}: prefix length must be 0 to {}",
+                        value, bits
+                    )
+                })?,
+            None => bits,
+        };
+
+        Ok(IpNetwork { network, prefix })
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(network: IpNetwork) -> Self {
+        format!("{}/{}", network.network, network.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(value: &str) -> IpNetwork {
+        IpNetwork::try_from(value.to_string()).unwrap()
+    }
+
+    fn contains(network_value: &str, ip: &str) -> bool {
+        network(network_value).contains(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn ranges_match_their_prefix() {
+        assert!(contains("10.0.0.0/8", "10.255.1.2"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.168.0.0/23", "192.168.1.200"));
+        assert!(!contains("192.168.0.0/23", "192.168.2.1"));
+        assert!(contains("3fff::/20", "3fff:fff::1"));
+        assert!(!contains("3fff::/20", "3fff:1000::1"));
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+    }
+
+    #[test]
+    fn single_addresses_match_exactly() {
+        assert!(contains("127.0.0.1", "127.0.0.1"));
+        assert!(!contains("127.0.0.1", "127.0.0.2"));
+        assert!(contains("::1", "::1"));
+        assert!(!contains("::1", "::2"));
+    }
+
+    #[test]
+    fn zero_prefixes_match_their_family_only() {
+        assert!(contains("0.0.0.0/0", "203.0.113.7"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+        assert!(!contains("::/0", "203.0.113.7"));
+    }
+
+    #[test]
+    fn invalid_networks_are_refused() {
+        for value in [
+            "10.0.0.0/abc",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "proxy.lan",
+        ] {
+            assert!(
+                IpNetwork::try_from(value.to_string()).is_err(),
+                "{} was accepted",
+                value
+            );
+        }
+    }
+}
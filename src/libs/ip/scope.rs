@@ -0,0 +1,209 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Where an address is routable, following the IANA special-purpose address registries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressScope {
+    Global,
+    Unspecified,
+    Loopback,
+    /// RFC 1918
+    Private,
+    /// RFC 6598 shared address space used by carrier-grade NAT
+    Shared,
+    LinkLocal,
+    /// IPv6 ULA, fc00::/7
+    UniqueLocal,
+    Documentation,
+    Multicast,
+    /// Benchmarking, reserved and other non-routable ranges
+    Reserved,
+}
+
+impl AddressScope {
+    pub fn is_global(&self) -> bool {
+        *self == AddressScope::Global
+    }
+}
+
+impl std::fmt::Display for AddressScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressScope::Global => write!(f, "global"),
+            AddressScope::Unspecified => write!(f, "unspecified"),
+            AddressScope::Loopback => write!(f, "loopback"),
+            AddressScope::Private => write!(f, "private"),
+            AddressScope::Shared => write!(f, "CGNAT"),
+            AddressScope::LinkLocal => write!(f, "link-local"),
+            AddressScope::UniqueLocal => write!(f, "unique local"),
+            AddressScope::Documentation => write!(f, "documentation"),
+            AddressScope::Multicast => write!(f, "multicast"),
+            AddressScope::Reserved => write!(f, "reserved"),
+        }
+    }
+}
+
+pub fn classify(ip: &IpAddr) -> AddressScope {
+    match ip {
+        IpAddr::V4(ip) => classify_v4(ip),
+        IpAddr::V6(ip) => classify_v6(ip),
+    }
+}
+
+fn classify_v4(ip: &Ipv4Addr) -> AddressScope {
+    let [a, b, _, _] = ip.octets();
+
+    if ip.is_unspecified() {
+        AddressScope::Unspecified
+    } else if ip.is_loopback() {
+        AddressScope::Loopback
+    } else if ip.is_private() {
+        AddressScope::Private
+    } else if a == 100 && (b & 0xc0) == 64 {
+        AddressScope::Shared
+    } else if ip.is_link_local() {
+        AddressScope::LinkLocal
+    } else if ip.is_documentation() {
+        AddressScope::Documentation
+    } else if ip.is_multicast() {
+        AddressScope::Multicast
+    } else if is_reserved_v4(ip) {
+        AddressScope::Reserved
+    } else {
+        AddressScope::Global
+    }
+}
+
+fn classify_v6(ip: &Ipv6Addr) -> AddressScope {
+    let segments = ip.segments();
+
+    if ip.is_unspecified() {
+        AddressScope::Unspecified
+    } else if ip.is_loopback() {
+        AddressScope::Loopback
+    } else if (segments[0] & 0xffc0) == 0xfe80 {
+        AddressScope::LinkLocal
+    } else if (segments[0] & 0xfe00) == 0xfc00 {
+        AddressScope::UniqueLocal
+    } else if ip.is_multicast() {
+        AddressScope::Multicast
+    } else if segments[..2] == [0x2001, 0x0db8] || (segments[0] == 0x3fff && segments[1] < 0x1000) {
+        AddressScope::Documentation
+    } else if is_reserved_v6(ip) {
+        AddressScope::Reserved
+    } else {
+        AddressScope::Global
+    }
+}
+
+fn is_reserved_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    // "This network"
+    a == 0
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // Deprecated 6to4 relay anycast
+        || (a == 192 && b == 88 && c == 99)
+        // Benchmarking
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved for future use and limited broadcast
+        || a >= 240
+}
+
+fn is_reserved_v6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // Anything outside global unicast 2000::/3, e.g. IPv4-mapped addresses
+    (segments[0] & 0xe000) != 0x2000
+        // IETF protocol assignments, 2001::/23
+        || (segments[0] == 0x2001 && segments[1] < 0x0200)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_scopes(cases: &[(&str, AddressScope)]) {
+        for (ip, expected) in cases {
+            assert_eq!(classify(&ip.parse().unwrap()), *expected, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn ipv4_special_purpose_ranges() {
+        assert_scopes(&[
+            ("0.0.0.0", AddressScope::Unspecified),
+            ("0.1.2.3", AddressScope::Reserved),
+            ("10.0.0.1", AddressScope::Private),
+            ("10.255.255.255", AddressScope::Private),
+            ("172.16.0.1", AddressScope::Private),
+            ("172.31.255.254", AddressScope::Private),
+            ("192.168.1.1", AddressScope::Private),
+            ("100.64.0.1", AddressScope::Shared),
+            ("100.127.255.254", AddressScope::Shared),
+            ("127.0.0.1", AddressScope::Loopback),
+            ("127.255.0.1", AddressScope::Loopback),
+            ("169.254.0.1", AddressScope::LinkLocal),
+            ("169.254.255.254", AddressScope::LinkLocal),
+            ("192.0.0.1", AddressScope::Reserved),
+            ("192.0.2.1", AddressScope::Documentation),
+            ("198.51.100.1", AddressScope::Documentation),
+            ("203.0.113.1", AddressScope::Documentation),
+            ("198.18.0.1", AddressScope::Reserved),
+            ("198.19.255.254", AddressScope::Reserved),
+            ("224.0.0.1", AddressScope::Multicast),
+            ("240.0.0.1", AddressScope::Reserved),
+            ("255.255.255.255", AddressScope::Reserved),
+        ]);
+    }
+
+    #[test]
+    fn ipv4_range_edges_are_global() {
+        assert_scopes(&[
+            ("1.1.1.1", AddressScope::Global),
+            ("8.8.8.8", AddressScope::Global),
+            ("11.0.0.1", AddressScope::Global),
+            ("100.63.255.254", AddressScope::Global),
+            ("100.128.0.1", AddressScope::Global),
+            ("172.32.0.1", AddressScope::Global),
+            ("192.0.1.1", AddressScope::Global),
+            ("198.17.255.254", AddressScope::Global),
+            ("198.20.0.1", AddressScope::Global),
+            ("223.255.255.254", AddressScope::Global),
+        ]);
+    }
+
+    #[test]
+    fn ipv6_special_purpose_ranges() {
+        assert_scopes(&[
+            ("::", AddressScope::Unspecified),
+            ("::1", AddressScope::Loopback),
+            ("fe80::1", AddressScope::LinkLocal),
+            ("febf:ffff::1", AddressScope::LinkLocal),
+            ("fc00::1", AddressScope::UniqueLocal),
+            ("fd12:3456:789a::1", AddressScope::UniqueLocal),
+            ("ff02::1", AddressScope::Multicast),
+            ("2001:db8::1", AddressScope::Documentation),
+            ("2001:db8:ffff:ffff::1", AddressScope::Documentation),
+            ("3fff::1", AddressScope::Documentation),
+            ("3fff:fff:ffff::1", AddressScope::Documentation),
+            ("::ffff:203.0.113.7", AddressScope::Reserved),
+            ("::ffff:8.8.8.8", AddressScope::Reserved),
+            ("2001::1", AddressScope::Reserved),
+            ("2001:1ff::1", AddressScope::Reserved),
+            ("4000::1", AddressScope::Reserved),
+            ("fec0::1", AddressScope::Reserved),
+        ]);
+    }
+
+    #[test]
+    fn ipv6_global_unicast() {
+        assert_scopes(&[
+            ("2606:4700:4700::1111", AddressScope::Global),
+            ("2a00:1450:4001::200e", AddressScope::Global),
+            ("2001:200::1", AddressScope::Global),
+            ("2001:db9::1", AddressScope::Global),
+            ("3fff:1000::1", AddressScope::Global),
+        ]);
+    }
+}
@@ -0,0 +1,296 @@
+use super::IpVersion;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Requests are resent at this interval until the source timeout expires.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Discovers the external address with a STUN Binding Request (RFC 5389).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StunSource {
+    pub name: String,
+
+    /// `host:port` pairs, tried in order until one answers
+    pub servers: Vec<String>,
+}
+
+impl Default for StunSource {
+    fn default() -> Self {
+        Self {
+            name: "Stun".to_string(),
+            servers: vec![
+                "stun.cloudflare.com:3478".to_string(),
+                "stun.l.google.com:19302".to_string(),
+            ],
+        }
+    }
+}
+
+impl StunSource {
+    /// Tries the servers in order within one deadline, name resolution
+    /// included. Each server gets an even share of the time left.
+    pub async fn lookup(
+        &self,
+        version: IpVersion,
+        timeout: Duration,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let deadline = Instant::now() + timeout;
+        let mut errors: Vec<String> = Vec::new();
+
+        for (i, server) in self.servers.iter().enumerate() {
+            let share = deadline.saturating_duration_since(Instant::now())
+                / (self.servers.len() - i) as u32;
+
+            match tokio::time::timeout(share, binding_request(server, version)).await {
+                Ok(Ok(ip)) => return Ok(ip.to_string()),
+                Ok(Err(e)) => {
+                    tracing::debug!("STUN request to {} failed: {}", server, e);
+                    errors.push(format!("{}: {}", server, e));
+                }
+                Err(_) => {
+                    tracing::debug!("STUN request to {} timed out", server);
+                    errors.push(format!("{}: timed out", server));
+                }
+            }
+        }
+
+        Err(format!("No STUN server answered ({})", errors.join(", ")).into())
+    }
+}
+
+/// Resends the request until an answer arrives; the caller bounds how long.
+async fn binding_request(
+    server: &str,
+    version: IpVersion,
+) -> Result<IpAddr, Box<dyn std::error::Error + Send + Sync>> {
+    let server = tokio::net::lookup_host(server)
+        .await?
+        .find(|addr| version.matches(&addr.ip()))
+        .ok_or_else(|| format!("{} has no {} address", server, version))?;
+
+    let transaction_id: [u8; 12] = rand::random();
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+
+    let mut buf = [0u8; 1024];
+    loop {
+        socket.send(&request).await?;
+
+        let Ok(len) = tokio::time::timeout(RETRANSMIT_INTERVAL, socket.recv(&mut buf)).await else {
+            continue;
+        };
+
+        // Anything that is not the answer to our request is ignored
+        if let Some(ip) = parse_response(&buf[..len?], &transaction_id) {
+            return Ok(ip);
+        }
+    }
+}
+
+fn parse_response(packet: &[u8], transaction_id: &[u8; 12]) -> Option<IpAddr> {
+    let header = packet.get(..20)?;
+    if u16::from_be_bytes([header[0], header[1]]) != BINDING_SUCCESS
+        || header[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || header[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let attributes = packet.get(20..20 + length)?;
+
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let kind = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let len = u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes.get(offset + 4..offset + 4 + len)?;
+
+        match kind {
+            XOR_MAPPED_ADDRESS => return decode_address(value, Some(transaction_id)),
+            MAPPED_ADDRESS => mapped = decode_address(value, None),
+            _ => {}
+        }
+
+        // Attribute values are padded to a multiple of four bytes
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+
+    mapped
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value; `transaction_id` is set for the XOR variant.
+fn decode_address(value: &[u8], transaction_id: Option<&[u8; 12]>) -> Option<IpAddr> {
+    let family = *value.get(1)?;
+    let address = value.get(4..)?;
+
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = transaction_id {
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+
+    match family {
+        0x01 => {
+            let mut octets: [u8; 4] = address.get(..4)?.try_into().ok()?;
+            octets.iter_mut().zip(mask).for_each(|(b, m)| *b ^= m);
+            Some(IpAddr::from(octets))
+        }
+        0x02 => {
+            let mut octets: [u8; 16] = address.get(..16)?.try_into().ok()?;
+            octets.iter_mut().zip(mask).for_each(|(b, m)| *b ^= m);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transaction ID of the RFC 5769 sample responses.
+    const TRANSACTION_ID: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    // 192.0.2.1:32853 from RFC 5769 section 2.2
+    const XOR_V4: [u8; 8] = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+
+    // 2001:db8:1234:5678:11:2233:4455:6677 port 32853 from RFC 5769 section 2.3
+    const XOR_V6: [u8; 20] = [
+        0x00, 0x02, 0xa1, 0x47, 0x01, 0x13, 0xa9, 0xfa, 0xa5, 0xd3, 0xf1, 0x79, 0xbc, 0x25, 0xf4,
+        0xb5, 0xbe, 0xd2, 0xb9, 0xd9,
+    ];
+
+    fn response(transaction_id: &[u8; 12], attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, value) in attributes {
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        packet.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        packet.extend_from_slice(transaction_id);
+        packet.extend_from_slice(&body);
+        packet
+    }
+
+    #[test]
+    fn decode_address_reverses_xor_v4() {
+        assert_eq!(
+            decode_address(&XOR_V4, Some(&TRANSACTION_ID)),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn decode_address_reverses_xor_v6() {
+        assert_eq!(
+            decode_address(&XOR_V6, Some(&TRANSACTION_ID)),
+            Some("2001:db8:1234:5678:11:2233:4455:6677".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn decode_address_reads_plain_mapped_address() {
+        let value = [0x00, 0x01, 0x80, 0x55, 203, 0, 113, 7];
+        assert_eq!(
+            decode_address(&value, None),
+            Some("203.0.113.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn decode_address_rejects_short_and_unknown_values() {
+        assert_eq!(decode_address(&XOR_V4[..6], Some(&TRANSACTION_ID)), None);
+        assert_eq!(decode_address(&XOR_V6[..12], Some(&TRANSACTION_ID)), None);
+        assert_eq!(decode_address(&[0x00, 0x03, 0, 0, 1, 2, 3, 4], None), None);
+    }
+
+    #[test]
+    fn parse_response_reads_xor_mapped_address() {
+        let packet = response(&TRANSACTION_ID, &[(XOR_MAPPED_ADDRESS, &XOR_V6)]);
+        assert_eq!(
+            parse_response(&packet, &TRANSACTION_ID),
+            Some("2001:db8:1234:5678:11:2233:4455:6677".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_response_prefers_xor_mapped_address() {
+        let software: &[u8] = b"test";
+        let mapped: &[u8] = &[0x00, 0x01, 0x80, 0x55, 10, 0, 0, 1];
+        let packet = response(
+            &TRANSACTION_ID,
+            &[
+                (0x8022, software),
+                (MAPPED_ADDRESS, mapped),
+                (XOR_MAPPED_ADDRESS, &XOR_V4),
+            ],
+        );
+        assert_eq!(
+            parse_response(&packet, &TRANSACTION_ID),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_response_falls_back_to_mapped_address() {
+        let mapped: &[u8] = &[0x00, 0x01, 0x80, 0x55, 203, 0, 113, 7];
+        let packet = response(&TRANSACTION_ID, &[(MAPPED_ADDRESS, mapped)]);
+        assert_eq!(
+            parse_response(&packet, &TRANSACTION_ID),
+            Some("203.0.113.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_response_ignores_other_transactions() {
+        let packet = response(&TRANSACTION_ID, &[(XOR_MAPPED_ADDRESS, &XOR_V4)]);
+        let mut other = TRANSACTION_ID;
+        other[11] ^= 1;
+        assert_eq!(parse_response(&packet, &other), None);
+    }
+
+    #[test]
+    fn parse_response_rejects_truncated_packets() {
+        let packet = response(&TRANSACTION_ID, &[(XOR_MAPPED_ADDRESS, &XOR_V6)]);
+        for len in [12, 20, 30, packet.len() - 1] {
+            assert_eq!(parse_response(&packet[..len], &TRANSACTION_ID), None);
+        }
+    }
+
+    #[test]
+    fn parse_response_rejects_other_messages() {
+        let mut packet = response(&TRANSACTION_ID, &[(XOR_MAPPED_ADDRESS, &XOR_V4)]);
+        // Binding error response
+        packet[..2].copy_from_slice(&0x0111u16.to_be_bytes());
+        assert_eq!(parse_response(&packet, &TRANSACTION_ID), None);
+    }
+}
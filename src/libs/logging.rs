@@ -1,14 +1,28 @@
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 pub struct Logger;
 
 impl Logger {
     pub fn init() {
+        Self::init_with_writer(std::io::stdout);
+    }
+
+    /// Logs to stderr, for commands that print their result to stdout.
+    pub fn init_stderr() {
+        Self::init_with_writer(std::io::stderr);
+    }
+
+    fn init_with_writer<W>(writer: W)
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
         let fmt_layer = fmt::layer()
             .json()
             // .with_span_events(fmt::format::FmtSpan::FULL) // to verbose, for now
             .with_level(true)
-            .with_line_number(true);
+            .with_line_number(true)
+            .with_writer(writer);
 
         let filter_layer = EnvFilter::try_from_default_env()
             // .unwrap_or_else(|_| EnvFilter::new("cloudflare_ddns=debug,axum=info,tower_http=info"));
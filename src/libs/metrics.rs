@@ -0,0 +1,466 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const PREFIX: &str = "cloudflare_ddns_";
+
+/// Upper bounds of the record update latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+type Labels = Vec<(&'static str, String)>;
+
+enum Value {
+    Counter(u64),
+    Gauge(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+struct Family {
+    help: &'static str,
+    series: BTreeMap<Labels, Value>,
+}
+
+static REGISTRY: Lazy<Mutex<BTreeMap<&'static str, Family>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+fn update(
+    name: &'static str,
+    help: &'static str,
+    labels: Labels,
+    initial: Value,
+    apply: impl FnOnce(&mut Value),
+) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let family = registry.entry(name).or_insert_with(|| Family {
+        help,
+        series: BTreeMap::new(),
+    });
+    apply(family.series.entry(labels).or_insert(initial));
+}
+
+fn inc(name: &'static str, help: &'static str, labels: Labels) {
+    update(name, help, labels, Value::Counter(0), |value| {
+        if let Value::Counter(count) = value {
+            *count += 1;
+        }
+    });
+}
+
+fn set(name: &'static str, help: &'static str, labels: Labels, to: f64) {
+    update(name, help, labels, Value::Gauge(0.0), |value| {
+        if let Value::Gauge(gauge) = value {
+            *gauge = to;
+        }
+    });
+}
+
+fn observe(name: &'static str, help: &'static str, labels: Labels, seconds: f64) {
+    let initial = Value::Histogram {
+        buckets: vec![0; LATENCY_BUCKETS.len()],
+        sum: 0.0,
+        count: 0,
+    };
+    update(name, help, labels, initial, |value| {
+        if let Value::Histogram {
+            buckets,
+            sum,
+            count,
+        } = value
+        {
+            for (bucket, bound) in buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if seconds <= bound {
+                    *bucket += 1;
+                }
+            }
+            *sum += seconds;
+            *count += 1;
+        }
+    });
+}
+
+/// Drops the series of a metric whose labels match, e.g. the previous address of an info metric.
+fn remove(name: &'static str, matches: impl Fn(&Labels) -> bool) {
+    if let Some(family) = REGISTRY.lock().unwrap().get_mut(name) {
+        family.series.retain(|labels, _| !matches(labels));
+    }
+}
+
+pub fn ip_check(source: &str, version: crate::libs::ip::IpVersion, success: bool) {
+    inc(
+        "ip_checks_total",
+        "External address lookups by source and outcome",
+        vec![
+            ("source", source.to_string()),
+            ("version", version.to_string()),
+            ("outcome", outcome(success).to_string()),
+        ],
+    );
+}
+
+/// Records the detected address, counting a change when it replaces a different one.
+pub fn external_ip(ip: &crate::libs::ip::IP, previous: Option<&str>) {
+    let version = ip.version.to_string();
+
+    if previous.is_some_and(|previous| previous != ip.ip) {
+        inc(
+            "ip_changes_total",
+            "Changes of the detected external address",
+            vec![("version", version.clone())],
+        );
+    }
+
+    remove("external_ip_info", |labels| {
+        labels.contains(&("version", version.clone()))
+    });
+    set(
+        "external_ip_info",
+        "Currently detected external address",
+        vec![
+            ("version", version),
+            ("ip", ip.ip.clone()),
+            ("source", ip.source.name().to_string()),
+        ],
+        1.0,
+    );
+}
+
+/// Counts a Cloudflare request attempt, `status` being the HTTP status or `error`.
+pub fn cloudflare_request(operation: &str, status: &str) {
+    inc(
+        "cloudflare_requests_total",
+        "Cloudflare API requests by operation and status",
+        vec![
+            ("operation", operation.to_string()),
+            ("status", status.to_string()),
+        ],
+    );
+}
+
+pub fn cloudflare_retry(operation: &str) {
+    inc(
+        "cloudflare_retries_total",
+        "Retried Cloudflare API requests by operation",
+        vec![("operation", operation.to_string())],
+    );
+}
+
+pub fn record_update(duration: Duration, success: bool) {
+    observe(
+        "record_update_duration_seconds",
+        "Time taken to publish a record",
+        vec![("outcome", outcome(success).to_string())],
+        duration.as_secs_f64(),
+    );
+}
+
+/// Marks the record as matching Cloudflare now.
+pub fn record_synced(zone: &str, record: &crate::libs::api::DnsRecord) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    set(
+        "record_last_sync_timestamp_seconds",
+        "Unix time a record was last found in sync with Cloudflare",
+        vec![
+            ("zone", zone.to_string()),
+            ("name", record.name.clone().unwrap_or_default()),
+            ("type", record.type_name()),
+        ],
+        now,
+    );
+}
+
+/// Drops the series of a record that is no longer managed, so it doesn't look stale.
+pub fn record_removed(zone: &str, name: &str, record_type: &str) {
+    remove("record_last_sync_timestamp_seconds", |labels| {
+        labels.contains(&("zone", zone.to_string()))
+            && labels.contains(&("name", name.to_string()))
+            && labels.contains(&("type", record_type.to_string()))
+    });
+}
+
+fn outcome(success: bool) -> &'static str {
+    if success { "success" } else { "failure" }
+}
+
+/// All metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    for (name, family) in registry.iter() {
+        if family.series.is_empty() {
+            continue;
+        }
+        let kind = match family.series.values().next() {
+            Some(Value::Counter(_)) => "counter",
+            Some(Value::Histogram { .. }) => "histogram",
+            _ => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {}{} {}", PREFIX, name, family.help);
+        let _ = writeln!(out, "# TYPE {}{} {}", PREFIX, name, kind);
+
+        for (labels, value) in &family.series {
+            match value {
+                Value::Counter(count) => {
+                    let _ = writeln!(
+                        out,
+                        "{}{}{} {}",
+                        PREFIX,
+                        name,
+                        format_labels(labels, None),
+                        count
+                    );
+                }
+                Value::Gauge(gauge) => {
+                    let _ = writeln!(
+                        out,
+                        "{}{}{} {}",
+                        PREFIX,
+                        name,
+                        format_labels(labels, None),
+                        gauge
+                    );
+                }
+                Value::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    for (bucket, bound) in buckets.iter().zip(LATENCY_BUCKETS) {
+                        let le = bound.to_string();
+                        let _ = writeln!(
+                            out,
+                            "{}{}_bucket{} {}",
+                            PREFIX,
+                            name,
+                            format_labels(labels, Some(&le)),
+                            bucket
+                        );
+                    }
+                    let _ = writeln!(
+                        out,
+                        "{}{}_bucket{} {}",
+                        PREFIX,
+                        name,
+                        format_labels(labels, Some("+Inf")),
+                        count
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{}{}_sum{} {}",
+                        PREFIX,
+                        name,
+                        format_labels(labels, None),
+                        sum
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{}{}_count{} {}",
+                        PREFIX,
+                        name,
+                        format_labels(labels, None),
+                        count
+                    );
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of the rendered metric, the registry being shared by all tests.
+    fn lines(name: &str) -> Vec<String> {
+        let metric = format!("{}{}", PREFIX, name);
+        render()
+            .lines()
+            .filter(|line| {
+                line.strip_prefix("# HELP ")
+                    .or_else(|| line.strip_prefix("# TYPE "))
+                    .unwrap_or(line)
+                    .strip_prefix(&metric)
+                    .is_some_and(|rest| rest.starts_with([' ', '{', '_']))
+            })
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn counters_have_help_and_type_lines() {
+        inc(
+            "test_counter_total",
+            "A counter",
+            vec![("zone", "a".to_string())],
+        );
+        inc(
+            "test_counter_total",
+            "A counter",
+            vec![("zone", "a".to_string())],
+        );
+        inc(
+            "test_counter_total",
+            "A counter",
+            vec![("zone", "b".to_string())],
+        );
+
+        assert_eq!(
+            lines("test_counter_total"),
+            [
+                "# HELP cloudflare_ddns_test_counter_total A counter",
+                "# TYPE cloudflare_ddns_test_counter_total counter",
+                "cloudflare_ddns_test_counter_total{zone=\"a\"} 2",
+                "cloudflare_ddns_test_counter_total{zone=\"b\"} 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn gauges_without_labels_have_no_braces() {
+        set("test_gauge", "A gauge", vec![], 1.5);
+
+        assert_eq!(
+            lines("test_gauge"),
+            [
+                "# HELP cloudflare_ddns_test_gauge A gauge",
+                "# TYPE cloudflare_ddns_test_gauge gauge",
+                "cloudflare_ddns_test_gauge 1.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let labels = || vec![("outcome", "success".to_string())];
+        observe("test_seconds", "A histogram", labels(), 0.2);
+        observe("test_seconds", "A histogram", labels(), 3.0);
+        observe("test_seconds", "A histogram", labels(), 120.0);
+
+        let lines = lines("test_seconds");
+        assert_eq!(lines[1], "# TYPE cloudflare_ddns_test_seconds histogram");
+        let bucket = |le: &str| {
+            format!(
+                "cloudflare_ddns_test_seconds_bucket{{outcome=\"success\",le=\"{}\"}}",
+                le
+            )
+        };
+        for (le, count) in [
+            ("0.1", 0),
+            ("0.25", 1),
+            ("2.5", 1),
+            ("5", 2),
+            ("60", 2),
+            ("+Inf", 3),
+        ] {
+            assert!(
+                lines.contains(&format!("{} {}", bucket(le), count)),
+                "missing bucket {} in {:#?}",
+                le,
+                lines
+            );
+        }
+        assert!(
+            lines.contains(
+                &"cloudflare_ddns_test_seconds_sum{outcome=\"success\"} 123.2".to_string()
+            )
+        );
+        assert!(
+            lines
+                .contains(&"cloudflare_ddns_test_seconds_count{outcome=\"success\"} 3".to_string())
+        );
+        assert_eq!(lines.len(), 2 + LATENCY_BUCKETS.len() + 3);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        set(
+            "test_escaped_info",
+            "Escaped labels",
+            vec![("name", "a\\b \"c\"\nd".to_string())],
+            1.0,
+        );
+
+        assert_eq!(
+            lines("test_escaped_info")[2],
+            r#"cloudflare_ddns_test_escaped_info{name="a\\b \"c\"\nd"} 1"#
+        );
+    }
+
+    #[test]
+    fn removed_records_lose_their_sync_timestamp() {
+        let labels = |name: &str| {
+            vec![
+                ("zone", "removed.example".to_string()),
+                ("name", name.to_string()),
+                ("type", "A".to_string()),
+            ]
+        };
+        set(
+            "record_last_sync_timestamp_seconds",
+            "Sync",
+            labels("www.removed.example"),
+            1.0,
+        );
+        set(
+            "record_last_sync_timestamp_seconds",
+            "Sync",
+            labels("mail.removed.example"),
+            1.0,
+        );
+
+        record_removed("removed.example", "www.removed.example", "A");
+
+        let rendered = render();
+        assert!(!rendered.contains("name=\"www.removed.example\""));
+        assert!(rendered.contains("name=\"mail.removed.example\""));
+    }
+
+    #[test]
+    fn removed_series_are_not_rendered() {
+        set(
+            "test_removed_info",
+            "Removed",
+            vec![("ip", "1".to_string())],
+            1.0,
+        );
+        remove("test_removed_info", |labels| {
+            labels.contains(&("ip", "1".to_string()))
+        });
+
+        assert!(lines("test_removed_info").is_empty());
+    }
+}
@@ -1,6 +1,14 @@
 pub mod api;
+pub mod auth;
 pub mod cf;
+pub mod client;
 pub mod config;
+pub mod credentials;
+pub mod health;
 pub mod ip;
 pub mod logging;
+pub mod metrics;
+pub mod plan;
+pub mod record;
 pub mod runner;
+pub mod supervisor;
@@ -0,0 +1,121 @@
+use crate::libs::api::{DnsRecord, get_api_client, get_zone, pick_live_record};
+use crate::libs::config::CONFIG;
+use crate::libs::ip::{IPSource, get_external_ip_for};
+
+/// Planned change of one configured record.
+pub enum Change {
+    Create(DnsRecord),
+    Update {
+        record: DnsRecord,
+        drift: Vec<String>,
+    },
+    Unchanged(DnsRecord),
+    /// The record would not be published, e.g. because no address was detected
+    Skip {
+        record: DnsRecord,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |record: &DnsRecord| {
+            format!(
+                "{} {}",
+                record.name.as_deref().unwrap_or_default(),
+                record.type_name()
+            )
+        };
+        let content = |record: &DnsRecord| {
+            record
+                .record_content()
+                .map_or("none".to_string(), |content| content.to_string())
+        };
+
+        match self {
+            Change::Create(record) => write!(f, "+ {} {}", describe(record), content(record)),
+            Change::Update { record, drift } => write!(
+                f,
+                "~ {} {} ({})",
+                describe(record),
+                content(record),
+                drift.join(", ")
+            ),
+            Change::Unchanged(record) => write!(f, "= {} {}", describe(record), content(record)),
+            Change::Skip { record, reason } => write!(f, "! {}: {}", describe(record), reason),
+        }
+    }
+}
+
+/// Desired state of a configured record. Address records get the address
+/// currently detected for them, unless clients push it.
+async fn desired_record(record: &DnsRecord) -> Result<DnsRecord, String> {
+    let mut desired = record.clone();
+
+    if let Some(version) = record.ip_version()
+        && !record.is_pushed()
+    {
+        let ip = match &record.ip_source {
+            Some(source) => IPSource::get_from(source, version)
+                .await
+                .map_err(|e| e.to_string())?,
+            None => {
+                get_external_ip_for(version).ok_or(format!("no {} address detected", version))?
+            }
+        };
+        desired.content = Some(ip.ip);
+        desired.check_address().map_err(|e| e.to_string())?;
+    }
+
+    desired.record_content().map_err(|e| e.to_string())?;
+    Ok(desired)
+}
+
+/// Compares the configured records of a zone with the ones in Cloudflare.
+pub async fn plan_zone(zone_name: &String) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+    let zone_id = get_zone(zone_name).await?;
+    let live = crate::libs::cf::list_records(&get_api_client(), zone_id, None, None).await?;
+
+    let records = {
+        let config = CONFIG.read().unwrap();
+        config
+            .get_zone_records(zone_name)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let mut changes = Vec::new();
+    for record in records {
+        let desired = match desired_record(&record).await {
+            Ok(desired) => desired,
+            Err(reason) => {
+                changes.push(Change::Skip { record, reason });
+                continue;
+            }
+        };
+
+        let name = desired.name.clone().unwrap_or_default();
+        let candidates = live
+            .iter()
+            .filter(|r| r.is(&name, Some(&desired.type_name())))
+            .cloned()
+            .collect();
+
+        changes.push(match pick_live_record(&desired, candidates) {
+            None => Change::Create(desired),
+            Some(existing) => {
+                let drift = desired.drift(&existing);
+                if drift.is_empty() {
+                    Change::Unchanged(desired)
+                } else {
+                    Change::Update {
+                        record: desired,
+                        drift,
+                    }
+                }
+            }
+        });
+    }
+
+    Ok(changes)
+}
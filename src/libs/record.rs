@@ -0,0 +1,128 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Typed content of a DNS record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RecordContent {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    TXT(String),
+    MX { exchange: String, priority: u16 },
+    SRV(SrvData),
+    CAA(CaaData),
+}
+
+/// SRV target; the `_service._proto` part goes into the record name.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SrvData {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CaaData {
+    #[serde(default)]
+    pub flags: u8,
+    /// `issue`, `issuewild` or `iodef`
+    pub tag: String,
+    pub value: String,
+}
+
+/// Structured `data` of SRV and CAA records, told apart by their fields.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum RecordData {
+    Srv(SrvData),
+    Caa(CaaData),
+}
+
+/// What to do when a record was changed outside of this tool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftAction {
+    Ignore,
+    #[default]
+    Warn,
+    /// Restore the configured content, TTL and proxied flag
+    Fix,
+}
+
+impl std::fmt::Display for RecordContent {
+    /// Zone file style, e.g. `10 mail.example.com` for MX records.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordContent::A(ip) => write!(f, "{}", ip),
+            RecordContent::AAAA(ip) => write!(f, "{}", ip),
+            RecordContent::CNAME(target) => write!(f, "{}", target),
+            RecordContent::TXT(text) => write!(f, "{:?}", text),
+            RecordContent::MX { exchange, priority } => write!(f, "{} {}", priority, exchange),
+            RecordContent::SRV(srv) => write!(
+                f,
+                "{} {} {} {}",
+                srv.priority, srv.weight, srv.port, srv.target
+            ),
+            RecordContent::CAA(caa) => write!(f, "{} {} {:?}", caa.flags, caa.tag, caa.value),
+        }
+    }
+}
+
+impl RecordContent {
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            RecordContent::A(_) => "A",
+            RecordContent::AAAA(_) => "AAAA",
+            RecordContent::CNAME(_) => "CNAME",
+            RecordContent::TXT(_) => "TXT",
+            RecordContent::MX { .. } => "MX",
+            RecordContent::SRV(_) => "SRV",
+            RecordContent::CAA(_) => "CAA",
+        }
+    }
+
+    /// Builds the typed content from the flat `content`, `priority` and `data` fields.
+    pub fn from_parts(
+        record_type: &str,
+        content: Option<&str>,
+        priority: Option<u16>,
+        data: Option<&RecordData>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let record_type = record_type.to_ascii_uppercase();
+        let content = || content.ok_or(format!("{} record content is missing", record_type));
+
+        match record_type.as_str() {
+            "A" => Ok(RecordContent::A(content()?.parse()?)),
+            "AAAA" => Ok(RecordContent::AAAA(content()?.parse()?)),
+            "CNAME" => Ok(RecordContent::CNAME(content()?.to_string())),
+            "TXT" => Ok(RecordContent::TXT(content()?.to_string())),
+            "MX" => Ok(RecordContent::MX {
+                exchange: content()?.to_string(),
+                priority: priority.ok_or("MX record priority is missing")?,
+            }),
+            "SRV" => match data {
+                Some(RecordData::Srv(srv)) => Ok(RecordContent::SRV(srv.clone())),
+                _ => Err("SRV record needs data with priority, weight, port and target".into()),
+            },
+            "CAA" => match data {
+                Some(RecordData::Caa(caa)) => Ok(RecordContent::CAA(caa.clone())),
+                _ => Err("CAA record needs data with flags, tag and value".into()),
+            },
+            _ => Err(format!("Unsupported record type: {}", record_type).into()),
+        }
+    }
+
+    /// Splits the content back into the flat `content`, `priority` and `data` fields.
+    pub fn into_parts(self) -> (Option<String>, Option<u16>, Option<RecordData>) {
+        match self {
+            RecordContent::A(ip) => (Some(ip.to_string()), None, None),
+            RecordContent::AAAA(ip) => (Some(ip.to_string()), None, None),
+            RecordContent::CNAME(target) => (Some(target), None, None),
+            RecordContent::TXT(text) => (Some(text), None, None),
+            RecordContent::MX { exchange, priority } => (Some(exchange), Some(priority), None),
+            RecordContent::SRV(srv) => (None, None, Some(RecordData::Srv(srv))),
+            RecordContent::CAA(caa) => (None, None, Some(RecordData::Caa(caa))),
+        }
+    }
+}
@@ -1,58 +1,318 @@
-use crate::libs::api::upsert_record;
+use crate::libs::api::{DnsRecord, get_api_client, get_zone, pick_live_record, upsert_record};
 use crate::libs::config::CONFIG;
-use crate::libs::ip::{IPSource, get_external_ip, set_external_ip};
-use tokio::time::{Duration, interval};
+use crate::libs::ip::{IPSource, IpVersion, get_external_ip_for, set_external_ip};
+use crate::libs::record::DriftAction;
+use crate::libs::supervisor::TaskResult;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant, interval};
+use tokio_util::sync::CancellationToken;
 
-pub async fn refresh_dns_loop(refresh_interval_secs: u64) {
-    let mut interval_timer = interval(Duration::from_secs(refresh_interval_secs));
+/// Refresh intervals between drift checks unless configured.
+const DEFAULT_DRIFT_INTERVALS: u32 = 5;
+
+static DRIFT_INTERVAL: OnceCell<Duration> = OnceCell::new();
+
+/// Current drift of each drifted record by zone, name and type, so a drift
+/// is only reported when it appears or changes.
+type DriftState = HashMap<(String, String, String), Vec<String>>;
+
+pub fn set_drift_interval(interval: Duration) {
+    if DRIFT_INTERVAL.set(interval).is_err() {
+        tracing::warn!("Drift interval was already configured");
+    }
+}
+
+/// Periodically re-detects the external IP, pushes changes to Cloudflare and,
+/// every drift interval, checks the managed records for drift.
+/// Shutdown is only observed between ticks, so updates already in flight are
+/// allowed to finish.
+pub async fn refresh_dns_loop(
+    refresh_interval_secs: u64,
+    shutdown: CancellationToken,
+) -> TaskResult {
+    let refresh_interval = Duration::from_secs(refresh_interval_secs);
+    let mut interval_timer = interval(refresh_interval);
+    crate::libs::health::set_refresh_interval(refresh_interval);
+
+    let drift_interval = DRIFT_INTERVAL
+        .get()
+        .copied()
+        .unwrap_or(refresh_interval * DEFAULT_DRIFT_INTERVALS);
+    // Unchanged records are only confirmed with Cloudflare by the drift check
+    crate::libs::health::set_sync_interval(drift_interval.max(refresh_interval));
+    let mut last_drift_check: Option<Instant> = None;
+    let mut drift_state = DriftState::new();
 
     loop {
-        interval_timer.tick().await;
-
-        match IPSource::get().await {
-            Ok(current_ip) => {
-                tracing::info!(
-                    "Current IP: {} from {}",
-                    current_ip.ip,
-                    current_ip.source.name()
-                );
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                tracing::info!("Refresh loop stopped");
+                return Ok(());
+            }
+            _ = interval_timer.tick() => {}
+        }
+        crate::libs::health::loop_ticked();
+
+        for version in [IpVersion::V4, IpVersion::V6] {
+            refresh_records(version).await;
+        }
+
+        if last_drift_check.is_none_or(|checked| checked.elapsed() >= drift_interval) {
+            last_drift_check = Some(Instant::now());
+            check_drift(&mut drift_state).await;
+        }
+    }
+}
+
+/// Seeds the stored external addresses before the server starts answering.
+pub async fn detect_external_ips() {
+    for version in [IpVersion::V4, IpVersion::V6] {
+        match IPSource::get(version).await {
+            Ok(ip) => {
+                tracing::info!("{}: {} from {}", version, ip.ip, ip.source.name());
+                set_external_ip(ip);
+            }
+            Err(e) => tracing::warn!("Failed to get {} address: {}", version, e),
+        }
+    }
+}
+
+/// Detects the external address for one family and updates the records of the
+/// matching type whose published content differs from it.
+async fn refresh_records(version: IpVersion) {
+    let config_snapshot = {
+        let config = CONFIG.read().unwrap();
+        config.records.clone()
+    };
+
+    refresh_source_records(version, &config_snapshot).await;
+
+    let follows_detection = |record: &DnsRecord| {
+        record.ip_version() == Some(version) && record.ip_source.is_none() && !record.is_pushed()
+    };
+    // Nothing to update, and hosts without IPv6 connectivity would otherwise fail every tick
+    if !config_snapshot.values().flatten().any(follows_detection) {
+        tracing::debug!("No {} records follow the detected address", version);
+        return;
+    }
+
+    let current_ip = match IPSource::get(version).await {
+        Ok(ip) => ip,
+        Err(e) => {
+            tracing::error!("Failed to get {} address: {}", version, e);
+            for (zone_name, records) in &config_snapshot {
+                for record in records.iter().filter(|record| follows_detection(record)) {
+                    crate::libs::health::record_failed(
+                        zone_name,
+                        record.name.as_deref().unwrap_or_default(),
+                        &record.type_name(),
+                    );
+                }
+            }
+            return;
+        }
+    };
+
+    tracing::info!(
+        "Current {} address: {} from {}",
+        version,
+        current_ip.ip,
+        current_ip.source.name()
+    );
+
+    if let Some(scope) = current_ip.scope().filter(|scope| !scope.is_global()) {
+        tracing::warn!(
+            "{} address {} from {} is {}, only records with allow_non_global will use it",
+            version,
+            current_ip.ip,
+            current_ip.source.name(),
+            scope
+        );
+    }
+
+    match get_external_ip_for(version) {
+        Some(last_known_ip) if last_known_ip.ip == current_ip.ip => {
+            tracing::info!("{} address hasn't changed.", version);
+        }
+        _ => {
+            tracing::info!(
+                "{} address changed, updating stored IP to: {} from {}",
+                version,
+                current_ip.ip,
+                current_ip.source.name()
+            );
+            set_external_ip(current_ip.clone());
+        }
+    }
+
+    // Compared against the published content rather than the last detected
+    // address, so records that drifted or failed to update are retried
+    for (zone_name, records) in config_snapshot {
+        for mut record in records {
+            if !follows_detection(&record) {
+                continue;
+            }
+            if record.content.as_ref() == Some(&current_ip.ip) {
+                crate::libs::health::record_current(&zone_name, &record);
+                continue;
+            }
+
+            record.content = Some(current_ip.ip.clone());
+
+            if let Err(e) = upsert_record(&zone_name, record).await {
+                tracing::error!("Error updating record: {}", e);
+            }
+        }
+    }
+}
+
+/// Updates records that pick their own IP source. Each source is asked once
+/// per tick and records are compared against their last published content.
+async fn refresh_source_records(
+    version: IpVersion,
+    config_snapshot: &HashMap<String, Vec<DnsRecord>>,
+) {
+    let mut detected: HashMap<String, Option<String>> = HashMap::new();
 
-                let ip_has_changed = {
-                    match get_external_ip() {
-                        Some(last_known_ip) if last_known_ip.ip == current_ip.ip => {
-                            tracing::info!("IP hasn't changed.");
-                            false
-                        }
-                        _ => {
-                            tracing::info!(
-                                "IP changed, updating stored IP to: {} from {}",
-                                current_ip.ip,
-                                current_ip.source.name()
-                            );
-                            set_external_ip(current_ip.clone());
-                            true
-                        }
+    for (zone_name, records) in config_snapshot {
+        for record in records {
+            let Some(source) = &record.ip_source else {
+                continue;
+            };
+            if record.ip_version() != Some(version) || record.is_pushed() {
+                continue;
+            }
+
+            if !detected.contains_key(source) {
+                let ip = match IPSource::get_from(source, version).await {
+                    Ok(ip) => {
+                        tracing::info!("Current {} address: {} from {}", version, ip.ip, source);
+                        Some(ip.ip)
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to get {} address from {}: {}", version, source, e);
+                        None
                     }
                 };
+                detected.insert(source.clone(), ip);
+            }
 
-                if ip_has_changed {
-                    let config_snapshot = {
-                        let config = CONFIG.read().unwrap();
-                        config.records.clone()
-                    };
+            let Some(Some(ip)) = detected.get(source) else {
+                crate::libs::health::record_failed(
+                    zone_name,
+                    record.name.as_deref().unwrap_or_default(),
+                    &record.type_name(),
+                );
+                continue;
+            };
+            if record.content.as_ref() == Some(ip) {
+                crate::libs::health::record_current(zone_name, record);
+                continue;
+            }
 
-                    for (zone_name, records) in config_snapshot {
-                        for mut record in records {
-                            record.content = Some(current_ip.ip.clone());
+            let mut record = record.clone();
+            record.content = Some(ip.clone());
 
-                            if let Err(e) = upsert_record(&zone_name, record).await {
-                                tracing::error!("Error updating record: {}", e);
-                            }
-                        }
+            if let Err(e) = upsert_record(zone_name, record).await {
+                tracing::error!("Error updating record: {}", e);
+            }
+        }
+    }
+}
+
+/// Compares the managed records with Cloudflare and reports or restores the
+/// ones changed outside of this tool, according to each record's `on_drift`.
+/// Drift that was already reported is not repeated until it changes.
+async fn check_drift(state: &mut DriftState) {
+    let config_snapshot = {
+        let config = CONFIG.read().unwrap();
+        config.records.clone()
+    };
+
+    for (zone_name, records) in config_snapshot {
+        // Records that were never published are left to the refresh above
+        let records: Vec<DnsRecord> = records
+            .into_iter()
+            .filter(|record| record.drift_action() != DriftAction::Ignore)
+            .filter(|record| record.record_content().is_ok())
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+
+        let zone_id = match get_zone(&zone_name).await {
+            Ok(zone_id) => zone_id,
+            Err(e) => {
+                tracing::error!("Failed to check drift in zone {}: {}", zone_name, e);
+                continue;
+            }
+        };
+
+        let live = match crate::libs::cf::list_records(&get_api_client(), zone_id, None, None).await
+        {
+            Ok(live) => live,
+            Err(e) => {
+                tracing::error!("Failed to list records in zone {}: {}", zone_name, e);
+                continue;
+            }
+        };
+
+        for mut record in records {
+            let name = record.name.clone().unwrap_or_default();
+            let record_type = record.type_name();
+
+            let existing = live
+                .iter()
+                .find(|r| record.id.is_some() && r.id == record.id)
+                .cloned()
+                .or_else(|| {
+                    let candidates = live
+                        .iter()
+                        .filter(|r| r.is(&name, Some(&record_type)))
+                        .cloned()
+                        .collect();
+                    pick_live_record(&record, candidates)
+                });
+
+            let drift = match &existing {
+                Some(existing) => record.drift(existing),
+                None => vec!["record is missing".to_string()],
+            };
+            let key = (zone_name.clone(), name.clone(), record_type.clone());
+            if drift.is_empty() {
+                if state.remove(&key).is_some() {
+                    tracing::info!("Record {} ({}) is back in sync", name, record_type);
+                }
+                crate::libs::health::record_synced(&zone_name, &record);
+                continue;
+            }
+            let reported = state.insert(key, drift.clone()).as_ref() == Some(&drift);
+
+            match record.drift_action() {
+                DriftAction::Ignore => {}
+                DriftAction::Warn if reported => {}
+                DriftAction::Warn => {
+                    tracing::warn!(
+                        "Record {} ({}) drifted: {}",
+                        name,
+                        record_type,
+                        drift.join(", ")
+                    );
+                }
+                DriftAction::Fix => {
+                    tracing::warn!(
+                        "Record {} ({}) drifted: {}, restoring it",
+                        name,
+                        record_type,
+                        drift.join(", ")
+                    );
+                    record.id = existing.and_then(|existing| existing.id);
+                    if let Err(e) = upsert_record(&zone_name, record).await {
+                        tracing::error!("Error restoring record: {}", e);
                     }
                 }
             }
-            Err(e) => tracing::error!("Failed to get IP: {}", e),
         }
     }
 }
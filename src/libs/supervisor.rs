@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::{Id, JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+
+pub type TaskResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// How long tasks get to finish in-flight work once shutdown was requested.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Runs the refresh loop, the HTTP server and the credentials watcher side by
+/// side until a shutdown signal arrives or one of them stops on its own. Any
+/// task exiting before shutdown was requested is treated as a failure.
+pub async fn run(bind: &str, refresh_interval: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let shutdown = CancellationToken::new();
+
+    let mut tasks = JoinSet::new();
+    let mut names: HashMap<Id, &'static str> = HashMap::new();
+
+    let handle = tasks.spawn(crate::libs::runner::refresh_dns_loop(
+        refresh_interval,
+        shutdown.clone(),
+    ));
+    names.insert(handle.id(), "refresh loop");
+
+    let handle = tasks.spawn(crate::web::server::Server::init(
+        bind.to_string(),
+        shutdown.clone(),
+    ));
+    names.insert(handle.id(), "server");
+
+    let handle = tasks.spawn(crate::libs::credentials::watch(shutdown.clone()));
+    names.insert(handle.id(), "credentials watcher");
+
+    let mut clean = tokio::select! {
+        _ = wait_for_signal() => {
+            tracing::info!("Shutdown signal received, stopping");
+            true
+        }
+        Some(result) = tasks.join_next_with_id() => {
+            let name = names[&task_id(&result)];
+            tracing::error!("{} exited unexpectedly", name);
+            outcome(name, result);
+            false
+        }
+    };
+
+    shutdown.cancel();
+
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE_PERIOD;
+    loop {
+        match tokio::time::timeout_at(deadline, tasks.join_next_with_id()).await {
+            Ok(Some(result)) => clean &= outcome(names[&task_id(&result)], result),
+            Ok(None) => break,
+            Err(_) => {
+                tracing::error!(
+                    "{} task(s) did not stop within {}s, aborting",
+                    tasks.len(),
+                    SHUTDOWN_GRACE_PERIOD.as_secs()
+                );
+                tasks.abort_all();
+                clean = false;
+                break;
+            }
+        }
+    }
+
+    if clean {
+        tracing::info!("Shutdown complete");
+        Ok(())
+    } else {
+        Err("one or more tasks did not shut down cleanly".into())
+    }
+}
+
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+fn task_id(result: &Result<(Id, TaskResult), JoinError>) -> Id {
+    match result {
+        Ok((id, _)) => *id,
+        Err(e) => e.id(),
+    }
+}
+
+fn outcome(name: &str, result: Result<(Id, TaskResult), JoinError>) -> bool {
+    match result {
+        Ok((_, Ok(()))) => true,
+        Ok((_, Err(e))) => {
+            tracing::error!("{} failed: {}", name, e);
+            false
+        }
+        Err(e) => {
+            tracing::error!("{} panicked: {}", name, e);
+            false
+        }
+    }
+}
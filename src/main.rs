@@ -1,4 +1,6 @@
 use clap::{Parser, Subcommand};
+use libs::credentials::{CredentialSource, Secret};
+use std::path::PathBuf;
 
 mod commands;
 mod libs;
@@ -13,6 +15,10 @@ struct Cli {
 
     #[clap(flatten)]
     options: Args,
+
+    /// Log the record changes instead of making them
+    #[arg(long, global = true)]
+    dry_run: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -31,61 +37,169 @@ enum Commands {
 
     /// Run in API mode (Kubernetes operator or controller)
     Api {
+        /// Optional configuration file for settings and initial records
+        #[arg(short, long)]
+        config: Option<String>,
+
         #[arg(short, long, default_value = "127.0.0.1:3000")]
         bind: String,
 
         #[arg(short, long, default_value = "60")]
         refresh_interval: u64,
     },
+
+    /// Print the records of existing zones as a configuration file, then exit.
+    /// A and AAAA records are only included when selected with --name or --type,
+    /// since they would be set to this host's address
+    Import {
+        /// Zone to import, can be repeated
+        #[arg(short, long, required = true)]
+        zone: Vec<String>,
+
+        /// Only import records with this name
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Only import records of this type
+        #[arg(short = 't', long = "type")]
+        record_type: Option<String>,
+    },
+
+    /// Show how the records in Cloudflare differ from the configuration, then exit
+    Plan {
+        #[arg(short, long, default_value = "./config.yaml")]
+        config: String,
+    },
 }
 
 #[derive(Debug, Parser)]
 struct Args {
-    /// Cloudflare API key
-    #[arg(env = "CF_API_KEY")]
-    cf_api_key: String,
+    /// Cloudflare global API key, prefer an API token
+    #[arg(env = "CF_API_KEY", hide_env_values = true)]
+    cf_api_key: Option<String>,
 
     /// Cloudflare API email
     #[arg(env = "CF_API_EMAIL")]
-    cf_api_email: String,
+    cf_api_email: Option<String>,
+
+    /// Cloudflare API token with DNS edit permission on the managed zones
+    #[arg(long, env = "CF_API_TOKEN", hide_env_values = true)]
+    cf_api_token: Option<String>,
+
+    /// File holding the Cloudflare API token, re-read when it changes
+    #[arg(long, env = "CF_API_TOKEN_FILE")]
+    cf_api_token_file: Option<PathBuf>,
+
+    /// File holding the Cloudflare global API key, re-read when it changes
+    #[arg(long, env = "CF_API_KEY_FILE")]
+    cf_api_key_file: Option<PathBuf>,
+
+    /// Retries of rate limited or failed Cloudflare API requests
+    #[arg(long, env = "CF_MAX_RETRIES", default_value = "5")]
+    cf_max_retries: u32,
+
+    /// Cloudflare API requests in flight at once
+    #[arg(long, env = "CF_MAX_CONCURRENCY", default_value = "4")]
+    cf_max_concurrency: usize,
+
+    /// Seconds between checks of the records for changes made outside of this tool,
+    /// defaults to five refresh intervals
+    #[arg(long, env = "DRIFT_INTERVAL", value_parser = clap::value_parser!(u64).range(1..))]
+    drift_interval: Option<u64>,
+
+    /// Drift check intervals a record may go without a successful sync before /readyz fails
+    #[arg(
+        long,
+        env = "READY_MAX_SYNC_INTERVALS",
+        default_value = "3",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    ready_max_sync_intervals: u32,
+}
+
+impl Args {
+    fn credentials(self) -> Result<CredentialSource, Box<dyn std::error::Error>> {
+        let token = match (self.cf_api_token, self.cf_api_token_file) {
+            (Some(_), Some(_)) => return Err("Set either an API token or a token file".into()),
+            (token, file) => token.map(Secret::Value).or(file.map(Secret::File)),
+        };
+        let key = match (self.cf_api_key, self.cf_api_key_file) {
+            (Some(_), Some(_)) => return Err("Set either an API key or a key file".into()),
+            (key, file) => key.map(Secret::Value).or(file.map(Secret::File)),
+        };
+
+        match (token, key, self.cf_api_email) {
+            (Some(token), key, _) => {
+                if key.is_some() {
+                    tracing::warn!("Both an API token and an API key are set, using the token");
+                }
+                Ok(CredentialSource::Token(token))
+            }
+            (None, Some(key), Some(email)) => Ok(CredentialSource::Key { email, key }),
+            _ => Err("Set CF_API_TOKEN(_FILE), or CF_API_KEY(_FILE) and CF_API_EMAIL".into()),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
-    libs::logging::Logger::init();
+    match cli.command {
+        Commands::Plan { .. } | Commands::Import { .. } => libs::logging::Logger::init_stderr(),
+        _ => libs::logging::Logger::init(),
+    }
 
-    match libs::api::init_cf(cli.options.cf_api_email, cli.options.cf_api_key).await {
+    libs::cf::set_dry_run(cli.dry_run);
+    if cli.dry_run {
+        tracing::warn!("Dry run, no changes will be made in Cloudflare");
+    }
+
+    libs::client::set_retry_policy(libs::client::RetryPolicy {
+        max_retries: cli.options.cf_max_retries,
+        max_concurrent: cli.options.cf_max_concurrency,
+    });
+
+    libs::health::set_max_sync_intervals(cli.options.ready_max_sync_intervals);
+
+    if let Some(drift_interval) = cli.options.drift_interval {
+        libs::runner::set_drift_interval(std::time::Duration::from_secs(drift_interval));
+    }
+
+    match libs::credentials::init(cli.options.credentials()?).await {
         Ok(_) => {
             tracing::info!("Cloudflare API client initialized");
         }
         Err(e) => {
             tracing::error!("Failed to initialize Cloudflare API client: {}", e);
+            return Err(e);
         }
     }
 
-    match crate::libs::ip::IPSource::get().await {
-        Ok(ip) => {
-            tracing::info!("IP: {} from {}", ip.ip, ip.source.name());
-            crate::libs::ip::set_external_ip(ip);
-        }
-        Err(e) => tracing::error!("Failed to get IP: {}", e),
-    }
-
     match cli.command {
         Commands::File {
             config,
             bind,
             refresh_interval,
         } => {
-            commands::file::run(&config, &bind, refresh_interval).await;
+            commands::file::run(&config, &bind, refresh_interval).await?;
         }
         Commands::Api {
+            config,
             bind,
             refresh_interval,
         } => {
-            commands::api::run(&bind, refresh_interval).await;
+            commands::api::run(config.as_deref(), &bind, refresh_interval).await?;
+        }
+        Commands::Import {
+            zone,
+            name,
+            record_type,
+        } => {
+            commands::import::run(&zone, name.as_deref(), record_type.as_deref()).await?;
+        }
+        Commands::Plan { config } => {
+            commands::plan::run(&config).await?;
         }
     }
 
@@ -0,0 +1,101 @@
+use super::error::ApiError;
+use crate::libs::auth::{Credentials, Scope};
+use axum::extract::{Path, Request};
+use axum::http::{HeaderValue, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+
+/// Authenticates the caller and checks it may use the zone and record in the path.
+/// The client is added to the request extensions so handlers can narrow their results.
+/// Changes are written to the audit log with the client that made them.
+pub async fn require_auth(
+    path: Option<Path<HashMap<String, String>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let auth = crate::libs::config::CONFIG.read().unwrap().api_auth.clone();
+
+    let method = request.method().clone();
+    let uri = request.uri().path().to_string();
+    let scope = match method {
+        Method::GET | Method::HEAD => Scope::Read,
+        _ => Scope::Write,
+    };
+
+    if !auth.is_enabled() {
+        if auth.allows_anonymous(scope) {
+            return next.run(request).await;
+        }
+        tracing::warn!(
+            "Rejected anonymous request {} {}, no api_auth clients are configured",
+            method,
+            uri
+        );
+        return unauthorized();
+    }
+
+    let credentials = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Credentials::from_header);
+    let Some(client) = credentials
+        .as_ref()
+        .and_then(|credentials| auth.authenticate(credentials))
+    else {
+        tracing::warn!("Rejected unauthenticated request {} {}", method, uri);
+        return unauthorized();
+    };
+
+    let params = path.map(|Path(params)| params).unwrap_or_default();
+    let record = params.get("record").map(String::as_str);
+    // Routes without a zone, like /metrics, cover all zones and records
+    let allowed = match params.get("zone_name") {
+        Some(zone) => client.allows(scope, zone, record),
+        None => client.allows_all(scope),
+    };
+
+    if !allowed {
+        tracing::warn!(
+            target: "audit",
+            "Denied {} {} to {}, it lacks {} access",
+            method,
+            uri,
+            client.name,
+            scope
+        );
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("{} may not {} {}", client.name, scope, uri),
+        )
+        .into_response();
+    }
+
+    request.extensions_mut().insert(client.clone());
+    let client = client.name.clone();
+    let response = next.run(request).await;
+
+    if scope == Scope::Write {
+        tracing::info!(
+            target: "audit",
+            "{} {} by {}: {}",
+            method,
+            uri,
+            client,
+            response.status()
+        );
+    }
+
+    response
+}
+
+fn unauthorized() -> Response {
+    let mut response =
+        ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid credentials").into_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static("Bearer, Basic realm=\"cloudflare-ddns\""),
+    );
+    response
+}
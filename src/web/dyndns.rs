@@ -0,0 +1,277 @@
+use crate::libs::api::DnsRecord;
+use crate::libs::auth::{AuthClient, Credentials, Scope};
+use crate::libs::cf::CloudflareError;
+use crate::libs::ip::IpVersion;
+use axum::extract::{ConnectInfo, Query};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use std::net::{IpAddr, SocketAddr};
+
+/// Parameters of a dyndns2 update, e.g. `?hostname=home.example.com&myip=203.0.113.7`.
+#[derive(serde::Deserialize)]
+pub struct UpdateQuery {
+    /// Comma separated host names
+    hostname: Option<String>,
+
+    /// Comma separated addresses, at most one per family. The caller's address when missing.
+    myip: Option<String>,
+}
+
+/// dyndns2 compatible update used by routers and NAS devices. Host names are
+/// matched with the configured A and AAAA records of their zone, which are set to
+/// the given addresses. Only pushed records can be updated this way.
+#[axum::debug_handler]
+pub async fn update_handler(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<UpdateQuery>,
+) -> Response {
+    let auth = crate::libs::config::CONFIG.read().unwrap().api_auth.clone();
+
+    let client = if auth.is_enabled() {
+        let credentials = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Credentials::from_header);
+        match credentials.and_then(|credentials| auth.authenticate(&credentials).cloned()) {
+            Some(client) => Some(client),
+            None => {
+                tracing::warn!("Rejected unauthenticated dyndns update");
+                return bad_auth();
+            }
+        }
+    } else if auth.allows_anonymous(Scope::Write) {
+        None
+    } else {
+        tracing::warn!("Rejected anonymous dyndns update, no api_auth clients are configured");
+        return bad_auth();
+    };
+
+    let hostnames: Vec<String> = query
+        .hostname
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(normalize_host)
+        .filter(|hostname| !hostname.is_empty())
+        .collect();
+    if hostnames.is_empty() {
+        return text("notfqdn");
+    }
+
+    let addresses = match &query.myip {
+        Some(myip) => {
+            let parsed: Result<Vec<IpAddr>, _> = myip
+                .split(',')
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty())
+                .map(str::parse)
+                .collect();
+            match parsed {
+                Ok(addresses) if one_per_family(&addresses) => addresses,
+                Ok(_) => {
+                    tracing::warn!(
+                        "dyndns update with several addresses of one family: {}",
+                        myip
+                    );
+                    return text("911");
+                }
+                Err(e) => {
+                    tracing::warn!("Invalid dyndns address {}: {}", myip, e);
+                    return text("911");
+                }
+            }
+        }
+        None => vec![super::peer::client_ip(peer, &headers)],
+    };
+
+    let mut lines = Vec::new();
+    for hostname in hostnames {
+        lines.push(update_host(&hostname, &addresses, client.as_ref()).await);
+    }
+
+    text(&lines.join("\n"))
+}
+
+/// Host names are matched in lowercase and without the trailing dot, e.g.
+/// `home.example.com` for `Home.Example.com.`.
+fn normalize_host(hostname: &str) -> String {
+    hostname.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Each address updates the record of its family, so a family can only be given once.
+fn one_per_family(addresses: &[IpAddr]) -> bool {
+    let v4 = addresses.iter().filter(|ip| ip.is_ipv4()).count();
+    v4 <= 1 && addresses.len() - v4 <= 1
+}
+
+/// Updates the records of one host and returns its dyndns2 answer.
+/// The host name is already normalized.
+async fn update_host(hostname: &str, addresses: &[IpAddr], client: Option<&AuthClient>) -> String {
+    let targets: Vec<(String, DnsRecord, IpAddr)> = {
+        let config = crate::libs::config::CONFIG.read().unwrap();
+        let Some((zone_name, records)) = zone_of(hostname, config.records.keys())
+            .and_then(|zone_name| config.records.get_key_value(zone_name))
+        else {
+            tracing::warn!(
+                "dyndns update for host {} outside the configured zones",
+                hostname
+            );
+            return "nohost".to_string();
+        };
+        addresses
+            .iter()
+            .filter_map(|ip| {
+                let record_type = IpVersion::of(ip).record_type();
+                records
+                    .iter()
+                    .find(|record| record.is(hostname, Some(record_type)))
+                    .map(|record| (zone_name.clone(), record.clone(), *ip))
+            })
+            .collect()
+    };
+
+    if targets.is_empty() {
+        tracing::warn!("dyndns update for unknown host {}", hostname);
+        return "nohost".to_string();
+    }
+
+    // The refresh loop would overwrite the address with the detected one
+    if let Some((_, record, _)) = targets.iter().find(|(_, record, _)| !record.is_pushed()) {
+        tracing::warn!(
+            "Refused dyndns update of {} ({}), set pushed on the record to update it this way",
+            hostname,
+            record.type_name()
+        );
+        return "nohost".to_string();
+    }
+
+    if let Some(client) = client
+        && let Some((zone_name, _, _)) = targets
+            .iter()
+            .find(|(zone_name, _, _)| !client.allows(Scope::Write, zone_name, Some(hostname)))
+    {
+        tracing::warn!(
+            target: "audit",
+            "Denied dyndns update of {} in {} to {}",
+            hostname,
+            zone_name,
+            client.name
+        );
+        return "nohost".to_string();
+    }
+
+    let client_name = client.map_or("anonymous", |client| client.name.as_str());
+    let mut changed = false;
+    let mut published = Vec::new();
+
+    for (zone_name, mut record, ip) in targets {
+        let ip = ip.to_string();
+        published.push(ip.clone());
+
+        if record.content.as_deref() == Some(ip.as_str()) {
+            continue;
+        }
+        record.content = Some(ip.clone());
+        if let Err(e) = crate::libs::api::upsert_record(&zone_name, record).await {
+            tracing::error!("dyndns update of {} to {} failed: {}", hostname, ip, e);
+            // Refused addresses are the caller's problem, not a DNS failure
+            return match e {
+                CloudflareError::InvalidRecord { .. } | CloudflareError::NotConfigured { .. } => {
+                    "911".to_string()
+                }
+                _ => "dnserr".to_string(),
+            };
+        }
+
+        tracing::info!(
+            target: "audit",
+            "dyndns update of {} to {} by {}",
+            hostname,
+            ip,
+            client_name
+        );
+        changed = true;
+    }
+
+    let status = if changed { "good" } else { "nochg" };
+    format!("{} {}", status, published.join(","))
+}
+
+/// The most specific configured zone the normalized host name belongs to, e.g. `home.example.com`
+/// rather than `example.com` for `nas.home.example.com`.
+fn zone_of<'a>(hostname: &str, zones: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    zones
+        .filter(|zone| {
+            let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+            hostname == zone || hostname.ends_with(&format!(".{}", zone))
+        })
+        .max_by_key(|zone| zone.len())
+}
+
+fn text(body: &str) -> Response {
+    body.to_string().into_response()
+}
+
+/// `badauth` with a Basic challenge, since some clients only send credentials when asked.
+fn bad_auth() -> Response {
+    let mut response = (StatusCode::UNAUTHORIZED, "badauth").into_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static("Basic realm=\"cloudflare-ddns\""),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names_are_normalized() {
+        assert_eq!(normalize_host(" Home.Example.COM. "), "home.example.com");
+        assert_eq!(normalize_host("nas.example.com"), "nas.example.com");
+    }
+
+    #[test]
+    fn addresses_are_limited_to_one_per_family() {
+        let addresses = |values: &[&str]| -> Vec<IpAddr> {
+            values.iter().map(|ip| ip.parse().unwrap()).collect()
+        };
+
+        assert!(one_per_family(&addresses(&["203.0.113.7"])));
+        assert!(one_per_family(&addresses(&["203.0.113.7", "2001:db8::7"])));
+        assert!(!one_per_family(&addresses(&[
+            "203.0.113.7",
+            "198.51.100.7"
+        ])));
+        assert!(!one_per_family(&addresses(&[
+            "2001:db8::7",
+            "203.0.113.7",
+            "2001:db8::8"
+        ])));
+    }
+
+    #[test]
+    fn hosts_belong_to_the_longest_matching_zone() {
+        let zones = [
+            "example.com".to_string(),
+            "home.example.com".to_string(),
+            "ample.com".to_string(),
+        ];
+
+        assert_eq!(
+            zone_of("nas.home.example.com", zones.iter()).map(String::as_str),
+            Some("home.example.com")
+        );
+        assert_eq!(
+            zone_of("home.example.com", zones.iter()).map(String::as_str),
+            Some("home.example.com")
+        );
+        assert_eq!(
+            zone_of(&normalize_host("WWW.Example.com."), zones.iter()).map(String::as_str),
+            Some("example.com")
+        );
+        assert_eq!(zone_of("www.example.org", zones.iter()), None);
+    }
+}
@@ -0,0 +1,78 @@
+use axum::Json;
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+/// Problem details (RFC 9457) returned by handlers that fail.
+#[derive(Debug, serde::Serialize)]
+pub struct ApiError {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: String,
+    #[serde(serialize_with = "serialize_status")]
+    status: StatusCode,
+    detail: String,
+
+    /// Set when a record operation failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<crate::libs::cf::ErrorKind>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cloudflare_errors: Vec<crate::libs::cf::ApiMessage>,
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        ApiError {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status,
+            detail: detail.into(),
+            error_kind: None,
+            cloudflare_errors: Vec::new(),
+        }
+    }
+}
+
+/// Picks the status by the kind of failure.
+impl From<crate::libs::cf::CloudflareError> for ApiError {
+    fn from(error: crate::libs::cf::CloudflareError) -> Self {
+        let kind = error.kind();
+        let status = match kind {
+            crate::libs::cf::ErrorKind::Validation => StatusCode::BAD_REQUEST,
+            crate::libs::cf::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            crate::libs::cf::ErrorKind::Conflict => StatusCode::CONFLICT,
+            crate::libs::cf::ErrorKind::RateLimited | crate::libs::cf::ErrorKind::Unavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            // Our credentials were rejected, not the caller's
+            crate::libs::cf::ErrorKind::Authentication | crate::libs::cf::ErrorKind::Other => {
+                StatusCode::BAD_GATEWAY
+            }
+        };
+
+        ApiError {
+            error_kind: Some(kind),
+            cloudflare_errors: error.messages().to_vec(),
+            ..ApiError::new(status, error.to_string())
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status;
+        let mut response = (status, Json(self)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
@@ -1,2 +1,6 @@
+pub mod auth;
+pub mod dyndns;
+pub mod error;
+pub mod peer;
 pub mod routes;
 pub mod server;
@@ -0,0 +1,137 @@
+use crate::libs::ip::network::IpNetwork;
+use axum::http::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Address of the caller. `X-Forwarded-For` entries are followed from the
+/// right for as long as the hop that added them is a trusted proxy.
+pub fn client_ip(peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let trusted = crate::libs::config::CONFIG
+        .read()
+        .unwrap()
+        .trusted_proxies
+        .clone();
+
+    forwarded_client(peer.ip(), headers, &trusted)
+}
+
+fn forwarded_client(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNetwork]) -> IpAddr {
+    let mut ip = canonical(peer);
+    if trusted.is_empty() {
+        return ip;
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+
+    for hop in forwarded.into_iter().rev() {
+        if !trusted.iter().any(|network| network.contains(&ip)) {
+            break;
+        }
+        // Entries further left can't be told apart from ones the client made up
+        let Ok(hop) = hop.trim().parse() else {
+            break;
+        };
+        ip = canonical(hop);
+    }
+
+    ip
+}
+
+/// IPv4 peers of a dual-stack listener show up as IPv4-mapped IPv6 addresses.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(networks: &[&str]) -> Vec<IpNetwork> {
+        networks
+            .iter()
+            .map(|network| IpNetwork::try_from(network.to_string()).unwrap())
+            .collect()
+    }
+
+    fn headers(forwarded: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in forwarded {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn client(peer: &str, forwarded: &[&str], networks: &[&str]) -> String {
+        forwarded_client(
+            peer.parse().unwrap(),
+            &headers(forwarded),
+            &trusted(networks),
+        )
+        .to_string()
+    }
+
+    #[test]
+    fn forwarded_headers_are_ignored_without_trusted_proxies() {
+        assert_eq!(client("10.0.0.1", &["203.0.113.9"], &[]), "10.0.0.1");
+    }
+
+    #[test]
+    fn forwarded_headers_from_untrusted_peers_are_ignored() {
+        assert_eq!(
+            client("198.51.100.4", &["203.0.113.9"], &["10.0.0.0/8"]),
+            "198.51.100.4"
+        );
+    }
+
+    #[test]
+    fn the_chain_is_walked_from_the_right_through_trusted_proxies() {
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &["192.0.2.1, 203.0.113.9, 10.0.0.2"],
+                &["10.0.0.0/8"]
+            ),
+            "203.0.113.9"
+        );
+        // Split over several headers, as added by separate proxies
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &["192.0.2.1, 203.0.113.9", "10.0.0.2"],
+                &["10.0.0.0/8"]
+            ),
+            "203.0.113.9"
+        );
+    }
+
+    #[test]
+    fn the_walk_stops_at_entries_that_are_not_addresses() {
+        assert_eq!(
+            client("10.0.0.1", &["192.0.2.1, unknown"], &["10.0.0.0/8"]),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn mapped_peers_are_matched_as_ipv4() {
+        assert_eq!(
+            client("::ffff:10.0.0.1", &["203.0.113.9"], &["10.0.0.0/8"]),
+            "203.0.113.9"
+        );
+    }
+
+    #[test]
+    fn fully_trusted_chains_end_at_the_leftmost_entry() {
+        assert_eq!(
+            client("10.0.0.1", &["10.0.0.3, 10.0.0.2"], &["10.0.0.0/8"]),
+            "10.0.0.3"
+        );
+    }
+}
@@ -1,4 +1,16 @@
-use axum::{Json, extract::Path};
+use super::error::ApiError;
+use crate::libs::auth::AuthClient;
+use crate::libs::ip::IpVersion;
+use axum::{
+    Extension, Json,
+    extract::{
+        ConnectInfo, Path, Query,
+        rejection::{JsonRejection, QueryRejection},
+    },
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(serde::Serialize)]
 pub struct ResponseRoot {
@@ -11,10 +23,28 @@ pub struct Response {
     ip: Option<crate::libs::ip::IP>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    records: Option<Vec<crate::libs::api::DnsRecord>>,
+    ipv6: Option<crate::libs::ip::IP>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+    records: Option<Vec<crate::libs::api::DnsRecord>>,
+}
+
+impl Response {
+    /// The records with the external addresses detected so far.
+    fn with_records(records: Vec<crate::libs::api::DnsRecord>) -> Self {
+        Response {
+            ip: crate::libs::ip::get_external_ip(),
+            ipv6: crate::libs::ip::get_external_ipv6(),
+            records: Some(records),
+        }
+    }
+}
+
+/// Picks one record type when several records share a name, e.g. `?type=TXT`.
+#[derive(serde::Deserialize)]
+pub struct RecordQuery {
+    #[serde(rename = "type")]
+    record_type: Option<String>,
 }
 
 #[axum::debug_handler]
@@ -26,121 +56,225 @@ pub async fn root_handler() -> Json<ResponseRoot> {
     Json(response)
 }
 
+/// Liveness probe: the process runs and the refresh loop keeps ticking.
+#[axum::debug_handler]
+pub async fn healthz_handler() -> impl IntoResponse {
+    health_response(crate::libs::health::liveness())
+}
+
+/// Readiness probe: records are being kept in sync with Cloudflare.
+#[axum::debug_handler]
+pub async fn readyz_handler() -> impl IntoResponse {
+    health_response(crate::libs::health::readiness())
+}
+
+fn health_response(
+    report: crate::libs::health::Report,
+) -> (StatusCode, Json<crate::libs::health::Report>) {
+    let status = if report.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+#[axum::debug_handler]
+pub async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::libs::metrics::render(),
+    )
+}
+
 #[axum::debug_handler]
 pub async fn get_record_handler(
     Path((zone_name, record)): Path<(String, String)>,
-) -> Json<Response> {
-    let record = match crate::libs::api::get_record(&zone_name, &record).await {
-        Ok(record) => record,
-        Err(e) => {
-            tracing::error!("Failed to get record: {}", e);
-            return Json(Response {
-                ip: None,
-                records: None,
-                error: Some(e.to_string()),
-            });
-        }
-    };
+    query: Result<Query<RecordQuery>, QueryRejection>,
+) -> Result<Json<Response>, ApiError> {
+    let Query(query) = query.map_err(query_error)?;
+    let record =
+        match crate::libs::api::get_record(&zone_name, &record, query.record_type.as_deref()).await
+        {
+            Ok(record) => record,
+            Err(e) => {
+                tracing::error!("Failed to get record: {}", e);
+                return Err(e.into());
+            }
+        };
 
-    let ip = crate::libs::ip::get_external_ip().unwrap();
+    Ok(Json(Response::with_records(vec![record])))
+}
 
-    let response = Response {
-        ip: Some(ip.clone()),
-        records: Some(Vec::from([record])),
-        error: None,
-    };
+fn query_error(rejection: QueryRejection) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, rejection.body_text())
+}
 
-    Json(response)
+/// Record to publish. Address records take `content`, the caller's address
+/// with `use_client_ip`, or else the detected external address. How the record
+/// is managed (`ip_source`, `allow_non_global`, `on_drift` and `pushed`) comes
+/// from its configuration and is ignored here.
+#[derive(serde::Deserialize)]
+pub struct UpsertPayload {
+    #[serde(flatten)]
+    record: crate::libs::api::DnsRecord,
+
+    #[serde(default)]
+    use_client_ip: bool,
 }
 
 #[axum::debug_handler]
 pub async fn upsert_record_handler(
     Path((zone_name, record)): Path<(String, String)>,
-    Json(mut payload): Json<crate::libs::api::DnsRecord>,
-) -> Json<Response> {
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    payload: Result<Json<UpsertPayload>, JsonRejection>,
+) -> Result<Json<Response>, ApiError> {
+    let (mut payload, use_client_ip) = match payload {
+        Ok(Json(payload)) => (payload.record, payload.use_client_ip),
+        Err(e) => return Err(ApiError::new(StatusCode::BAD_REQUEST, e.body_text())),
+    };
+
     tracing::info!("POST payload: {:#?}", payload);
 
-    // Get current external IP
-    let ip = match crate::libs::ip::get_external_ip() {
-        Some(ip) => ip,
-        None => {
-            return Json(Response {
-                ip: None,
-                records: None,
-                error: Some("Could not retrieve external IP".into()),
-            });
+    payload.name = Some(record.clone());
+    // The id only comes from the config or a live lookup by name and type, so a
+    // caller limited to some record names can't point the update at another record
+    payload.id = None;
+
+    let pushed_ip = match (use_client_ip, &payload.content) {
+        (true, Some(_)) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Set either content or use_client_ip",
+            ));
         }
+        (true, None) => Some(super::peer::client_ip(peer, &headers).to_string()),
+        (false, content) => content.clone(),
     };
 
-    // Prepare payload
-    payload.name = Some(record.clone());
-    payload.content = Some(ip.ip.clone());
+    // A given address decides between A and AAAA when no type is set
+    if payload.record_type.is_none()
+        && let Some(ip) = pushed_ip
+            .as_deref()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+    {
+        payload.record_type = Some(IpVersion::of(&ip).record_type().to_string());
+    }
+    managed_by_config(&zone_name, &mut payload);
+
+    // Address records get the given or the caller's address, else the current external IP.
+    // Given addresses are pushed so the refresh loop keeps them. Other types are published as given
+    if let Some(version) = payload.ip_version() {
+        match pushed_ip {
+            Some(ip) => {
+                let ip: IpAddr = match ip.parse() {
+                    Ok(ip) => ip,
+                    Err(_) => {
+                        return Err(ApiError::new(
+                            StatusCode::BAD_REQUEST,
+                            format!("{} is not an IP address", ip),
+                        ));
+                    }
+                };
+
+                payload.content = Some(ip.to_string());
+                payload.pushed = Some(true);
+            }
+            None => {
+                let ip = detected_ip(&payload, version).await?;
+                payload.content = Some(ip.ip.clone());
+                payload.record_type = Some(version.record_type().to_string());
+            }
+        }
+    }
 
     // Perform upsert using your unified logic
     let result = crate::libs::api::upsert_record(&zone_name, payload).await;
 
     match result {
-        Ok(updated) => Json(Response {
-            ip: Some(ip),
-            records: Some(vec![updated]),
-            error: None,
-        }),
-        Err(e) => Json(Response {
-            ip: Some(ip),
-            records: None,
-            error: Some(format!("Upsert failed: {}", e)),
-        }),
+        Ok(updated) => Ok(Json(Response::with_records(vec![updated]))),
+        Err(e) => Err(e.into()),
     }
 }
 
+/// Takes how the record is managed from the configured record with the same name
+/// and type, or the defaults for a new one, so callers can't lift the address checks.
+fn managed_by_config(zone_name: &String, record: &mut crate::libs::api::DnsRecord) {
+    let config = crate::libs::config::CONFIG.read().unwrap();
+    let existing = config.get_zone_record(
+        zone_name,
+        record.name.as_deref().unwrap_or_default(),
+        Some(&record.type_name()),
+    );
+
+    record.ip_source = existing.and_then(|existing| existing.ip_source.clone());
+    record.allow_non_global = existing.and_then(|existing| existing.allow_non_global);
+    record.on_drift = existing.and_then(|existing| existing.on_drift);
+    record.pushed = existing.and_then(|existing| existing.pushed);
+}
+
+/// The external address detected for the record, from its own source when it has one.
+async fn detected_ip(
+    record: &crate::libs::api::DnsRecord,
+    version: IpVersion,
+) -> Result<crate::libs::ip::IP, ApiError> {
+    let ip = match &record.ip_source {
+        Some(source) => match crate::libs::ip::IPSource::get_from(source, version).await {
+            Ok(ip) => Some(ip),
+            Err(e) => {
+                tracing::error!("Failed to get {} address from {}: {}", version, source, e);
+                None
+            }
+        },
+        None => crate::libs::ip::get_external_ip_for(version),
+    };
+
+    ip.ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Could not retrieve external {} address", version),
+        )
+    })
+}
+
 #[axum::debug_handler]
-pub async fn list_handler(Path(zone_name): Path<String>) -> Json<Response> {
+pub async fn list_handler(
+    Path(zone_name): Path<String>,
+    client: Option<Extension<AuthClient>>,
+) -> Result<Json<Response>, ApiError> {
     let records = match crate::libs::api::list_records(&zone_name).await {
         Ok(records) => records,
         Err(e) => {
             tracing::error!("Failed to get records: {}", e);
-            return Json(Response {
-                ip: None,
-                records: None,
-                error: Some(e.to_string()),
-            });
+            return Err(e.into());
         }
     };
-
-    let ip = crate::libs::ip::get_external_ip().unwrap();
-
-    let response = Response {
-        ip: Some(ip),
-        records: Some(records),
-        error: None,
+    // Clients limited to some record names only see those
+    let records = match client {
+        Some(Extension(client)) => client.readable_records(&zone_name, records),
+        None => records,
     };
 
-    Json(response)
+    Ok(Json(Response::with_records(records)))
 }
 
 #[axum::debug_handler]
 pub async fn delete_record_handler(
     Path((zone_name, record)): Path<(String, String)>,
-) -> Json<Response> {
-    let ip = crate::libs::ip::get_external_ip().unwrap();
+    query: Result<Query<RecordQuery>, QueryRejection>,
+) -> Result<Json<Response>, ApiError> {
+    let Query(query) = query.map_err(query_error)?;
+    let record =
+        match crate::libs::api::delete_record(&zone_name, &record, query.record_type.as_deref())
+            .await
+        {
+            Ok(record) => record,
+            Err(e) => {
+                tracing::error!("Failed to delete record: {}", e);
+                return Err(e.into());
+            }
+        };
 
-    let record = match crate::libs::api::delete_record(&zone_name, &record).await {
-        Ok(record) => record,
-        Err(e) => {
-            tracing::error!("Failed to delete record: {}", e);
-            return Json(Response {
-                ip: Some(ip),
-                records: None,
-                error: Some(e.to_string()),
-            });
-        }
-    };
-
-    let response = Response {
-        ip: Some(ip),
-        records: Some(Vec::from([record])),
-        error: None,
-    };
-
-    Json(response)
+    Ok(Json(Response::with_records(vec![record])))
 }
@@ -1,7 +1,13 @@
+use super::auth::require_auth;
+use super::dyndns::update_handler;
 use super::routes::{
-    delete_record_handler, get_record_handler, list_handler, root_handler, upsert_record_handler,
+    delete_record_handler, get_record_handler, healthz_handler, list_handler, metrics_handler,
+    readyz_handler, root_handler, upsert_record_handler,
 };
-use axum::{Router, routing::delete, routing::get, routing::post};
+use crate::libs::auth::Scope;
+use crate::libs::supervisor::TaskResult;
+use axum::{Router, middleware, routing::delete, routing::get, routing::post};
+use tokio_util::sync::CancellationToken;
 use tower_http::trace::{DefaultOnRequest, TraceLayer};
 // use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse};
 use tracing::Level;
@@ -9,29 +15,54 @@ use tracing::Level;
 pub struct Server {}
 
 impl Server {
-    pub async fn init(bind: &String) {
+    pub async fn init(bind: String, shutdown: CancellationToken) -> TaskResult {
         let app = Router::new().layer(TraceLayer::new_for_http());
 
-        let app = app
-            .route("/", get(root_handler))
+        let auth = crate::libs::config::CONFIG.read().unwrap().api_auth.clone();
+        if auth.allows_anonymous(Scope::Write) {
+            tracing::warn!("No api_auth clients configured, the API is open to anyone");
+        } else if !auth.is_enabled() {
+            tracing::warn!(
+                "No api_auth clients configured, changes are refused unless api_auth.allow_anonymous is set"
+            );
+        }
+
+        let records = Router::new()
             .route("/{zone_name}", get(list_handler))
             .route("/{zone_name}/{record}", get(get_record_handler))
             .route("/{zone_name}/{record}", post(upsert_record_handler))
             .route("/{zone_name}/{record}", delete(delete_record_handler))
+            .route_layer(middleware::from_fn(require_auth));
+
+        // Metrics name every managed record, so they need read access to all zones
+        let metrics = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .route_layer(middleware::from_fn(require_auth));
+
+        let app = app
+            .route("/", get(root_handler))
+            .route("/healthz", get(healthz_handler))
+            .route("/readyz", get(readyz_handler))
+            .route("/nic/update", get(update_handler))
+            .merge(records)
+            .merge(metrics)
             .layer(
                 TraceLayer::new_for_http()
                     // .make_span_with(DefaultMakeSpan::new().level(Level::INFO)) // to verbose, for now
                     .on_request(DefaultOnRequest::new().level(Level::INFO)), // .on_response(DefaultOnResponse::new().level(Level::INFO)),
             );
 
-        let listener = match tokio::net::TcpListener::bind(bind)
-            .await
-            .expect("failed to bind")
-        {
-            listener => listener,
-        };
+        let listener = tokio::net::TcpListener::bind(&bind).await?;
+
+        tracing::info!("listening on {}", listener.local_addr()?);
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await?;
 
-        println!("listening on {}", listener.local_addr().unwrap());
-        axum::serve(listener, app).await.unwrap();
+        tracing::info!("Server stopped");
+        Ok(())
     }
 }